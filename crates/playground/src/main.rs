@@ -5,122 +5,143 @@ use world::*;
 fn main() {
     let entity_map: Rc<RefCell<HashMap<EntityId, Entity>>> = Default::default();
 
-    let mut world = World::new(|entity, notification| {
-        let mut entity_map = entity_map.borrow_mut();
-
-        match notification {
-            Notification::Spawn {
-                allegiance,
-                armor,
-                health,
-                position,
-                reactions,
-            } => {
-                entity_map.insert(
-                    entity,
-                    Entity {
-                        id: entity,
-                        allegiance: allegiance.copied(),
-                        armor: armor.copied(),
-                        health: health.copied(),
-                        position: position.copied(),
-                        reactions: reactions.clone(),
-                    },
-                );
-            }
-            Notification::Destroy => {
-                entity_map.remove(&entity);
-            }
-            Notification::ChangeAllegiance(allegiance) => {
-                entity_map.get_mut(&entity).unwrap().allegiance = allegiance.copied();
-            }
-            Notification::ChangeArmor(armor) => {
-                entity_map.get_mut(&entity).unwrap().armor = armor.copied();
-            }
-            Notification::ChangeHealth(health) => {
-                entity_map.get_mut(&entity).unwrap().health = health.copied();
-            }
-            Notification::ChangePosition(position) => {
-                entity_map.get_mut(&entity).unwrap().position = position.copied();
-            }
-            Notification::ChangeReactions(reactions) => {
-                entity_map.get_mut(&entity).unwrap().reactions = reactions.clone();
-            }
-        }
-    });
+    let config = WorldConfig {
+        record_actions: true,
+        record_history: true,
+        ..Default::default()
+    };
 
-    world.perform(
-        Action::Spawn {
-            allegiance: Some(Allegiance::Player),
-            armor: Some(Armor { current: 5 }),
-            health: Some(Health {
-                current: 10,
-                max: 10,
-            }),
-            position: Some(Position { x: 0, y: 0 }),
-            reactions: vec![Reaction::OpportunityAttack { damage_amount: 10 }],
+    let mut world = World::with_config(
+        |entity, notification| {
+            let mut entity_map = entity_map.borrow_mut();
+
+            match notification {
+                Notification::Spawn {
+                    allegiance,
+                    armor,
+                    health,
+                    position,
+                    reactions,
+                } => {
+                    entity_map.insert(
+                        entity,
+                        Entity {
+                            id: entity,
+                            allegiance: allegiance.copied(),
+                            armor: armor.copied(),
+                            health: health.copied(),
+                            position: position.copied(),
+                            reactions: reactions.clone(),
+                        },
+                    );
+                }
+                Notification::Destroy => {
+                    entity_map.remove(&entity);
+                }
+                Notification::ChangeAllegiance(allegiance) => {
+                    entity_map.get_mut(&entity).unwrap().allegiance = allegiance.copied();
+                }
+                Notification::ChangeArmor(armor) => {
+                    entity_map.get_mut(&entity).unwrap().armor = armor.copied();
+                }
+                Notification::ChangeHealth(health) => {
+                    entity_map.get_mut(&entity).unwrap().health = health.copied();
+                }
+                Notification::ChangePosition(position) => {
+                    entity_map.get_mut(&entity).unwrap().position = position.copied();
+                }
+                Notification::ChangeReactions(reactions) => {
+                    entity_map.get_mut(&entity).unwrap().reactions = reactions.clone();
+                }
+                Notification::StateHash(_) => {}
+            }
         },
-        WORLD_ENTITY,
-        WORLD_ENTITY,
-        0,
+        config,
     );
 
-    world.perform(
-        Action::Spawn {
-            allegiance: Some(Allegiance::Golem),
-            armor: Some(Armor { current: 0 }),
-            health: Some(Health { current: 3, max: 3 }),
-            position: Some(Position { x: 0, y: 5 }),
-            reactions: vec![
-                Reaction::Reinforce { armor_amount: 3 },
-                Reaction::Spite { damage_amount: 50 },
-            ],
-        },
-        WORLD_ENTITY,
-        WORLD_ENTITY,
-        0,
-    );
+    let player = world
+        .perform(
+            Action::Spawn {
+                allegiance: Some(Allegiance::Player),
+                armor: Some(Armor { current: 5 }),
+                health: Some(Health {
+                    current: 10,
+                    max: 10,
+                }),
+                position: Some(Position { x: 0, y: 0 }),
+                reactions: vec![Reaction::OpportunityAttack { damage_amount: 10 }],
+                reaction_priority: 0,
+            },
+            WORLD_ENTITY,
+            WORLD_ENTITY,
+        )
+        .expect("spawn failed")
+        .spawned()
+        .next()
+        .unwrap();
+
+    let golem = world
+        .perform(
+            Action::Spawn {
+                allegiance: Some(Allegiance::Golem),
+                armor: Some(Armor { current: 0 }),
+                health: Some(Health { current: 3, max: 3 }),
+                position: Some(Position { x: 0, y: 5 }),
+                reactions: vec![
+                    Reaction::Reinforce { armor_amount: 3 },
+                    Reaction::Spite { damage_amount: 50 },
+                ],
+                reaction_priority: 0,
+            },
+            WORLD_ENTITY,
+            WORLD_ENTITY,
+        )
+        .expect("spawn failed")
+        .spawned()
+        .next()
+        .unwrap();
 
     #[cfg(debug_assertions)]
     for entity in entity_map.borrow().values() {
         println!("{entity:?}");
     }
 
-    let player = EntityId(1);
-    let golem = EntityId(2);
-
-    world.perform(Action::Damage { amount: 1 }, player, golem, 0);
+    let _ = world.perform(Action::Damage { amount: 1 }, player, golem);
 
-    let query = EntityQuery {
-        allegiance_filter: ComponentFilter::Include(&[Allegiance::Golem]),
-        position_filter: ComponentFilter::Include(&[Position { x: 0, y: 5 }]),
-    };
+    let query = EntityQuery::Filter(EntityFilter {
+        allegiance_filter: ComponentFilter::Include(vec![Allegiance::Golem]),
+        position_filter: ComponentFilter::Include(vec![Position { x: 0, y: 5 }]),
+        ..Default::default()
+    });
 
-    world.perform_with_query(Action::Damage { amount: 1 }, player, query, 0);
-    world.perform(Action::GainArmor { amount: 5 }, player, player, 0);
+    let _ = world.perform_with_query(Action::Damage { amount: 1 }, player, query);
+    let _ = world.perform(Action::GainArmor { amount: 5 }, player, player);
 
-    world.perform(
+    let _ = world.perform(
         Action::Move {
             to_position: Position { x: 0, y: 0 },
         },
         golem,
         golem,
-        0,
     );
 
-    world.perform(
+    let _ = world.perform(
         Action::Move {
             to_position: Position { x: 0, y: 1 },
         },
         golem,
         golem,
-        0,
     );
 
     #[cfg(debug_assertions)]
     for entity in entity_map.borrow().values() {
         println!("{entity:?}");
     }
+
+    world.undo();
+    world.redo();
+
+    assert!(world.verify_replay());
 }
 
 #[allow(dead_code)]
@@ -1,7 +1,11 @@
-use crate::{log_with_indentation, systems::components::*, EntityId, Event, Notification, World};
+use crate::{
+    log_with_indentation, systems::components::*, ActionError, ActionOutcome, Change,
+    ComponentKind, DeathTiming, EntityId, EntitySet, EntitySnapshot, Event, Lifecycle,
+    Notification, Revision, Target, World, WORLD_ENTITY,
+};
 
-#[cfg_attr(debug_assertions, derive(Debug))]
-#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
 pub enum Action {
     Spawn {
         allegiance: Option<Allegiance>,
@@ -9,6 +13,9 @@ pub enum Action {
         health: Option<Health>,
         position: Option<Position>,
         reactions: Vec<Reaction>,
+        // Orders the reactions of the entity before the reactions of entities with a lower
+        // reaction priority, among reactions with the same `Reaction::priority`.
+        reaction_priority: i64,
     },
     Destroy,
     Move {
@@ -20,6 +27,44 @@ pub enum Action {
     GainArmor {
         amount: i64,
     },
+    Heal {
+        amount: i64,
+    },
+    ModifyMaxHealth {
+        amount: i64,
+    },
+    AddReaction {
+        reaction: Reaction,
+    },
+    RemoveReaction {
+        reaction: Reaction,
+    },
+    Silence,
+    ChangeAllegiance {
+        to: Allegiance,
+        temporary: bool,
+    },
+    RevertAllegiance,
+}
+
+#[derive(Default)]
+pub struct Chain {
+    pub actions_performed: u64,
+    // The reactor and reaction the budget ran out at.
+    pub aborted: Option<(EntityId, Reaction)>,
+    // Entities whose BeforeDestroy reactions are resolving.
+    pub destroying: EntitySet,
+    pub changes: Vec<(EntityId, Change)>,
+    // Entities without health left that wait for the death phase, in the order they died, along
+    // with the source of the action that killed them.
+    pub dying: Vec<(EntityId, EntityId)>,
+    pub dying_entities: EntitySet,
+    // After-events of top-level actions, collected while the targets of a simultaneous query are
+    // acted on.
+    pub deferred_events: Option<Vec<(Event, EntityId, EntityId)>>,
+    // The state before the chain of every entity index it touched, if history is recorded.
+    pub revision: Option<Revision>,
+    pub touched: EntitySet,
 }
 
 impl<NotificationHandler> World<NotificationHandler>
@@ -31,9 +76,242 @@ where
         action: Action,
         source: EntityId,
         target: EntityId,
+    ) -> Result<ActionOutcome, ActionError> {
+        self.start_chain();
+        self.log_action(action.clone(), source, Target::Entity(target));
+
+        let result = self
+            .check_source(&source)
+            .and_then(|_| self.execute(action, source, target, 0));
+
+        self.process_deaths();
+        self.finish_chain(result)
+    }
+
+    // Destroyed entities cannot act, and neither can ids that were never spawned.
+    pub(crate) fn check_source(&self, source: &EntityId) -> Result<(), ActionError> {
+        match self.lifecycle(source) {
+            None => Err(ActionError::SourceMissing),
+            Some(Lifecycle::Destroyed) => Err(ActionError::EntityDestroyed),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn start_chain(&mut self) {
+        self.chain = Chain {
+            revision: self.start_revision(),
+            ..Default::default()
+        };
+    }
+
+    pub(crate) fn finish_chain(
+        &mut self,
+        result: Result<(), ActionError>,
+    ) -> Result<ActionOutcome, ActionError> {
+        let chain = std::mem::take(&mut self.chain);
+
+        if let Some(revision) = chain.revision {
+            self.finish_revision(revision);
+        }
+
+        if self.config.notify_state_hash {
+            self.notification_handler
+                .notify(WORLD_ENTITY, Notification::StateHash(self.state_hash()));
+        }
+
+        let outcome = ActionOutcome {
+            actions_performed: chain.actions_performed,
+            changes: chain.changes,
+        };
+
+        if let Some((reactor, reaction)) = chain.aborted {
+            return Err(ActionError::BudgetExceeded {
+                reactor,
+                reaction,
+                outcome,
+            });
+        }
+
+        result?;
+        Ok(outcome)
+    }
+
+    fn record(&mut self, entity: EntityId, change: Change) {
+        self.chain.changes.push((entity, change))
+    }
+
+    // Inserts the entity into the systems. The registry must already hold it.
+    pub(crate) fn insert_entity(&mut self, entity: EntitySnapshot) {
+        let EntitySnapshot {
+            id: entity,
+            allegiance,
+            original_allegiance,
+            armor,
+            health,
+            position,
+            reactions,
+            reaction_priority,
+        } = entity;
+
+        self.notification_handler.notify(
+            entity,
+            Notification::Spawn {
+                allegiance: allegiance.as_ref(),
+                armor: armor.as_ref(),
+                health: health.as_ref(),
+                position: position.as_ref(),
+                reactions: &reactions,
+            },
+        );
+
+        if let Some(allegiance) = allegiance {
+            self.allegiance_system.insert(entity, allegiance);
+        }
+
+        if let Some(original_allegiance) = original_allegiance {
+            self.allegiance_system
+                .insert_original(entity, original_allegiance);
+        }
+
+        if let Some(armor) = armor {
+            self.armor_system.insert(entity, armor);
+        }
+
+        if let Some(health) = health {
+            self.health_system.insert(entity, health);
+        }
+
+        if let Some(position) = position {
+            self.position_system.insert(entity, position);
+        }
+
+        let spawn_sequence = self.registry.spawn_sequence(&entity);
+
+        self.reaction_system
+            .insert(entity, reactions, reaction_priority, spawn_sequence);
+    }
+
+    pub(crate) fn remove_entity(&mut self, entity: EntityId) {
+        self.registry.destroy(&entity);
+        self.notification_handler
+            .notify(entity, Notification::Destroy);
+
+        self.allegiance_system.remove(&entity);
+        self.armor_system.remove(&entity);
+        self.health_system.remove(&entity);
+        self.position_system.remove(&entity);
+        self.reaction_system.remove(&entity);
+    }
+
+    // Emits an after-event, unless it belongs to a top-level action of a simultaneous query.
+    fn emit_after(
+        &mut self,
+        mut event: Event,
+        source: EntityId,
+        target: EntityId,
         stack_depth: u64,
     ) {
-        log_with_indentation!(stack_depth, "[Action] {source:?} -> {target:?} {action:?}");
+        if let (0, Some(deferred_events)) = (stack_depth, &mut self.chain.deferred_events) {
+            deferred_events.push((event, source, target));
+            return;
+        }
+
+        self.emit(&mut event, source, target, stack_depth)
+    }
+
+    fn is_dead(&self, entity: &EntityId) -> bool {
+        self.registry.contains(entity)
+            && !self.chain.destroying.contains(entity)
+            && self
+                .health_system
+                .health(entity)
+                .is_some_and(|health| health.current <= 0)
+    }
+
+    // Destroys the target if it has no health left, or marks it as dying until the death phase.
+    fn destroy_if_dead(
+        &mut self,
+        source: EntityId,
+        target: EntityId,
+        stack_depth: u64,
+    ) -> Result<(), ActionError> {
+        if !self.is_dead(&target) || self.chain.dying_entities.contains(&target) {
+            return Ok(());
+        }
+
+        let deferred = stack_depth == 0 && self.chain.deferred_events.is_some();
+
+        if let (DeathTiming::AfterAction, false) = (self.config.death_timing, deferred) {
+            return self.execute(Action::Destroy, source, target, stack_depth);
+        }
+
+        self.chain.dying.push((source, target));
+        self.chain.dying_entities.insert(&target);
+        Ok(())
+    }
+
+    // Destroys dying entities in the order they died, including entities that die while others
+    // are destroyed. Entities that regained health in the meantime survive.
+    pub(crate) fn process_deaths(&mut self) {
+        while !self.chain.dying.is_empty() {
+            for (source, target) in std::mem::take(&mut self.chain.dying) {
+                self.chain.dying_entities.remove(&target);
+
+                if self.is_dead(&target) {
+                    let _ = self.execute(Action::Destroy, source, target, 0);
+                }
+            }
+        }
+    }
+
+    fn reactions(&self, entity: &EntityId) -> Vec<Reaction> {
+        self.reaction_system
+            .reactions(entity)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn execute(
+        &mut self,
+        action: Action,
+        source: EntityId,
+        target: EntityId,
+        stack_depth: u64,
+    ) -> Result<(), ActionError> {
+        // Spawns ignore their target, so any target is accepted. The world entity is only a source
+        // otherwise.
+        if !matches!(action, Action::Spawn { .. }) {
+            if target == WORLD_ENTITY {
+                return Err(ActionError::TargetMissing);
+            }
+
+            match self.lifecycle(&target) {
+                None => return Err(ActionError::TargetMissing),
+                Some(Lifecycle::Destroyed) => return Err(ActionError::EntityDestroyed),
+                _ if self.chain.destroying.contains(&target) => {
+                    return Err(ActionError::EntityDestroyed)
+                }
+                _ => {}
+            }
+        }
+
+        self.chain.actions_performed += 1;
+
+        // Spawns touch the index of the entity they are about to spawn.
+        let touched_index = match action {
+            Action::Spawn { .. } => self.registry.next_index(),
+            _ => Some(target.index),
+        };
+
+        if let Some(index) = touched_index {
+            self.touch(index);
+        }
+
+        log_with_indentation!(
+            self,
+            stack_depth,
+            "[Action] {source:?} -> {target:?} {action:?}"
+        );
 
         match action {
             Action::Spawn {
@@ -42,79 +320,334 @@ where
                 health,
                 position,
                 reactions,
+                reaction_priority,
             } => {
-                let entity = self.next_entity;
-                self.next_entity = EntityId(entity.0 + 1);
-
-                (self.notification_handler)(
-                    entity,
-                    Notification::Spawn {
-                        allegiance: allegiance.as_ref(),
-                        armor: armor.as_ref(),
-                        health: health.as_ref(),
-                        position: position.as_ref(),
-                        reactions: &reactions,
-                    },
-                );
-
-                if let Some(allegiance) = allegiance {
-                    self.allegiance_system.insert(entity, allegiance);
-                }
+                let Some(entity) = self.registry.spawn() else {
+                    return Err(ActionError::EntityLimitReached);
+                };
 
-                if let Some(armor) = armor {
-                    self.armor_system.insert(entity, armor);
-                }
+                self.insert_entity(EntitySnapshot {
+                    id: entity,
+                    allegiance,
+                    original_allegiance: None,
+                    armor,
+                    health,
+                    position,
+                    reactions,
+                    reaction_priority,
+                });
+                self.record(entity, Change::Spawn);
+            }
+            Action::Destroy => {
+                // The target is dying while its BeforeDestroy reactions resolve, and refuses other
+                // actions, so the chain cannot destroy it again.
+                self.chain.destroying.insert(&target);
 
-                if let Some(health) = health {
-                    self.health_system.insert(entity, health);
-                }
+                let mut event = Event::BeforeDestroy { prevented: false };
+                self.emit(&mut event, source, target, stack_depth);
 
-                if let Some(position) = position {
-                    self.position_system.insert(entity, position);
+                if event.is_prevented() {
+                    self.chain.destroying.remove(&target);
+                    return Ok(());
                 }
 
-                self.reaction_system.insert(entity, reactions);
-            }
-            Action::Destroy => {
-                (self.notification_handler)(target, Notification::Destroy);
-                self.emit(&Event::BeforeDestroy, source, target, stack_depth);
-
-                self.allegiance_system.remove(&target);
-                self.armor_system.remove(&target);
-                self.health_system.remove(&target);
-                self.position_system.remove(&target);
-                self.reaction_system.remove(&target);
+                self.record(target, Change::Destroy);
+                self.remove_entity(target);
+                self.chain.destroying.remove(&target);
             }
             Action::Move { to_position } => {
                 let Some(from_position) = self.position_system.position(&target).copied() else {
-                    return;
+                    return Err(ActionError::ComponentMissing(ComponentKind::Position));
+                };
+
+                let mut event = Event::BeforeMove {
+                    to_position,
+                    prevented: false,
+                };
+
+                self.emit(&mut event, source, target, stack_depth);
+
+                let Event::BeforeMove {
+                    to_position,
+                    prevented: false,
+                } = event
+                else {
+                    return Ok(());
                 };
 
                 self.position_system.move_to(target, to_position);
 
-                self.emit(
-                    &Event::AfterMove { from_position },
+                if from_position != to_position {
+                    self.record(
+                        target,
+                        Change::Position {
+                            from: from_position,
+                            to: to_position,
+                        },
+                    );
+                }
+
+                self.emit_after(
+                    Event::AfterMove { from_position },
                     source,
                     target,
                     stack_depth,
                 )
             }
             Action::Damage { amount } => {
+                if self.health_system.health(&target).is_none() {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Health));
+                }
+
+                let mut event = Event::BeforeDamage { amount };
+                self.emit(&mut event, source, target, stack_depth);
+
+                let Event::BeforeDamage {
+                    amount: amount @ 1..,
+                } = event
+                else {
+                    return Ok(());
+                };
+
+                let Some(from_health) = self.health_system.health(&target).copied() else {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Health));
+                };
+
+                let from_armor = self.armor_system.armor(&target).copied();
                 let overflow_damage = self.armor_system.lose(target, amount).unwrap_or(amount);
 
-                let Some(is_alive) = self.health_system.lose(target, overflow_damage) else {
-                    return;
+                if let (Some(from), Some(&to)) = (from_armor, self.armor_system.armor(&target)) {
+                    if from != to {
+                        self.record(target, Change::Armor { from, to });
+                    }
+                }
+
+                let Some(to_health) = self.health_system.lose(target, overflow_damage) else {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Health));
                 };
 
+                if from_health != to_health {
+                    self.record(
+                        target,
+                        Change::Health {
+                            from: from_health,
+                            to: to_health,
+                        },
+                    );
+                }
+
                 if overflow_damage > 0 {
-                    self.emit(&Event::AfterDamage, source, target, stack_depth)
+                    self.emit_after(Event::AfterDamage, source, target, stack_depth)
                 }
 
-                if !is_alive {
-                    self.perform(Action::Destroy, source, target, stack_depth)
+                self.destroy_if_dead(source, target, stack_depth)?
+            }
+            Action::GainArmor { amount } => {
+                let Some(from) = self.armor_system.armor(&target).copied() else {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Armor));
+                };
+
+                if let Some(to) = self.armor_system.gain(target, amount) {
+                    if from != to {
+                        self.record(target, Change::Armor { from, to });
+                    }
                 }
             }
-            Action::GainArmor { amount } => self.armor_system.gain(target, amount),
+            Action::Heal { amount } => {
+                let Some(from) = self.health_system.health(&target).copied() else {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Health));
+                };
+
+                let Some(to) = self.health_system.gain(target, amount) else {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Health));
+                };
+
+                if from != to {
+                    self.record(target, Change::Health { from, to });
+
+                    self.emit_after(
+                        Event::AfterHeal {
+                            amount: to.current - from.current,
+                        },
+                        source,
+                        target,
+                        stack_depth,
+                    )
+                }
+            }
+            Action::ModifyMaxHealth { amount } => {
+                let Some(from) = self.health_system.health(&target).copied() else {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Health));
+                };
+
+                let Some(to) = self.health_system.modify_max(target, amount) else {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Health));
+                };
+
+                if from != to {
+                    self.record(target, Change::Health { from, to });
+                }
+
+                self.destroy_if_dead(source, target, stack_depth)?
+            }
+            Action::AddReaction { reaction } => {
+                let from = self.reactions(&target);
+                self.reaction_system.add(target, reaction);
+                let to = self.reactions(&target);
+
+                self.record(target, Change::Reactions { from, to });
+            }
+            Action::RemoveReaction { reaction } => {
+                let from = self.reactions(&target);
+
+                if !self.reaction_system.remove_reaction(target, reaction) {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Reactions));
+                }
+
+                let to = self.reactions(&target);
+                self.record(target, Change::Reactions { from, to });
+            }
+            Action::Silence => {
+                let from = self.reactions(&target);
+
+                if from.is_empty() {
+                    return Ok(());
+                }
+
+                self.reaction_system.clear(target);
+
+                self.record(
+                    target,
+                    Change::Reactions {
+                        from,
+                        to: Vec::new(),
+                    },
+                );
+            }
+            Action::ChangeAllegiance { to, temporary } => {
+                self.change_allegiance(to, temporary, source, target, stack_depth)?
+            }
+            Action::RevertAllegiance => {
+                if self.allegiance_system.allegiance(&target).is_none() {
+                    return Err(ActionError::ComponentMissing(ComponentKind::Allegiance));
+                }
+
+                // Nothing to revert unless the allegiance was changed temporarily.
+                let Some(&original_allegiance) =
+                    self.allegiance_system.original_allegiance(&target)
+                else {
+                    return Ok(());
+                };
+
+                self.change_allegiance(original_allegiance, false, source, target, stack_depth)?
+            }
         }
+
+        Ok(())
+    }
+
+    fn change_allegiance(
+        &mut self,
+        to: Allegiance,
+        temporary: bool,
+        source: EntityId,
+        target: EntityId,
+        stack_depth: u64,
+    ) -> Result<(), ActionError> {
+        let Some(from) = self.allegiance_system.change(target, to, temporary) else {
+            return Err(ActionError::ComponentMissing(ComponentKind::Allegiance));
+        };
+
+        if from != to {
+            self.record(target, Change::Allegiance { from, to });
+
+            self.emit_after(
+                Event::AfterChangeAllegiance {
+                    from_allegiance: from,
+                },
+                source,
+                target,
+                stack_depth,
+            )
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::*;
+
+    fn spawn(world: &mut World<impl Fn(EntityId, Notification)>) -> EntityId {
+        world
+            .perform(
+                Action::Spawn {
+                    allegiance: Some(Allegiance::Golem),
+                    armor: None,
+                    health: Some(Health { current: 1, max: 1 }),
+                    position: None,
+                    reactions: Vec::new(),
+                    reaction_priority: 0,
+                },
+                WORLD_ENTITY,
+                WORLD_ENTITY,
+            )
+            .unwrap()
+            .spawned()
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn deaths_after_chain_follow_death_order() {
+        let notifications: Rc<RefCell<Vec<(EntityId, &str)>>> = Default::default();
+
+        let config = WorldConfig {
+            death_timing: DeathTiming::AfterChain,
+            quiet: true,
+            ..Default::default()
+        };
+
+        let mut world = World::with_config(
+            |entity, notification| match notification {
+                Notification::ChangeHealth(_) => {
+                    notifications.borrow_mut().push((entity, "damage"))
+                }
+                Notification::Destroy => notifications.borrow_mut().push((entity, "destroy")),
+                _ => {}
+            },
+            config,
+        );
+
+        let first = spawn(&mut world);
+        let second = spawn(&mut world);
+        let third = spawn(&mut world);
+
+        // Later spawns are damaged, and die, first.
+        let query = EntityQuery::Select {
+            query: Box::new(EntityQuery::And(Vec::new())),
+            order: Order::SpawnOrder,
+            descending: true,
+            limit: None,
+        };
+
+        world
+            .perform_with_query(Action::Damage { amount: 1 }, WORLD_ENTITY, query)
+            .unwrap();
+
+        assert_eq!(
+            *notifications.borrow(),
+            [
+                (third, "damage"),
+                (second, "damage"),
+                (first, "damage"),
+                (third, "destroy"),
+                (second, "destroy"),
+                (first, "destroy"),
+            ]
+        );
     }
 }
@@ -0,0 +1,339 @@
+use crate::Position;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy)]
+pub enum Anchor {
+    Position(Position),
+    // The position of the action source. Areas anchored to a source without a position are empty.
+    Source,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy)]
+pub enum Metric {
+    // Diagonal steps count as one, so a radius covers a square.
+    Chebyshev,
+    // Diagonal steps count as two, so a radius covers a diamond.
+    Manhattan,
+    // Straight-line distance, so a radius covers a circle.
+    Euclidean,
+}
+
+impl Metric {
+    pub fn within(&self, from: &Position, to: &Position, distance: i64) -> bool {
+        let distance = i128::from(distance);
+
+        match self {
+            Metric::Euclidean if distance < 0 => false,
+            Metric::Euclidean => self.distance_key(from, to) <= distance * distance,
+            _ => self.distance_key(from, to) <= distance,
+        }
+    }
+
+    // Orders positions the same way as their distance. Euclidean distances are squared, so they
+    // stay integers. Keys are computed in i128, so they do not overflow for any i64 positions,
+    // except squared distances beyond i128, which saturate.
+    pub fn distance_key(&self, from: &Position, to: &Position) -> i128 {
+        let (dx, dy) = offset(from, to);
+        let (dx, dy) = (dx.abs(), dy.abs());
+
+        match self {
+            Metric::Chebyshev => dx.max(dy),
+            Metric::Manhattan => dx + dy,
+            Metric::Euclidean => dx.saturating_mul(dx).saturating_add(dy.saturating_mul(dy)),
+        }
+    }
+}
+
+fn offset(from: &Position, to: &Position) -> (i128, i128) {
+    (
+        i128::from(to.x) - i128::from(from.x),
+        i128::from(to.y) - i128::from(from.y),
+    )
+}
+
+// A set of board positions. Every area includes the positions on its edges.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy)]
+pub enum Area {
+    Radius {
+        center: Anchor,
+        radius: i64,
+        metric: Metric,
+    },
+    // Axis-aligned, between any two opposite corners.
+    Rectangle {
+        corner: Position,
+        opposite_corner: Position,
+    },
+    // The positions a straight line passes through, including both ends.
+    Line {
+        from: Anchor,
+        to: Position,
+    },
+    // Positions within `range` of the origin and within 45 degrees of `direction`, which is an
+    // offset from the origin. The origin itself is excluded.
+    Cone {
+        origin: Anchor,
+        direction: Position,
+        range: i64,
+    },
+}
+
+impl Anchor {
+    fn resolve(&self, source_position: Option<&Position>) -> Option<Position> {
+        match self {
+            Anchor::Position(position) => Some(*position),
+            Anchor::Source => source_position.copied(),
+        }
+    }
+}
+
+impl Area {
+    // Returns whether the area contains the position, given the position of the action source.
+    pub fn contains(&self, position: &Position, source_position: Option<&Position>) -> bool {
+        match self {
+            Area::Radius {
+                center,
+                radius,
+                metric,
+            } => center
+                .resolve(source_position)
+                .is_some_and(|center| metric.within(&center, position, *radius)),
+            Area::Rectangle {
+                corner,
+                opposite_corner,
+            } => {
+                (corner.x.min(opposite_corner.x)..=corner.x.max(opposite_corner.x))
+                    .contains(&position.x)
+                    && (corner.y.min(opposite_corner.y)..=corner.y.max(opposite_corner.y))
+                        .contains(&position.y)
+            }
+            Area::Line { from, to } => from
+                .resolve(source_position)
+                .is_some_and(|from| line_contains(&from, to, position)),
+            Area::Cone {
+                origin,
+                direction,
+                range,
+            } => origin.resolve(source_position).is_some_and(|origin| {
+                let (dx, dy) = offset(&origin, position);
+                let (direction_x, direction_y) = (i128::from(direction.x), i128::from(direction.y));
+
+                let dot = (dx * direction_x).saturating_add(dy * direction_y);
+                let cross = (dx * direction_y).saturating_sub(dy * direction_x);
+
+                // tan(angle) <= tan(45 degrees), without dividing.
+                dot > 0
+                    && cross.unsigned_abs() <= dot.unsigned_abs()
+                    && Metric::Euclidean.within(&origin, position, *range)
+            }),
+        }
+    }
+}
+
+// The line steps one position at a time along its longer axis, rounding the other axis to the
+// nearest position (halves round up).
+fn line_contains(from: &Position, to: &Position, position: &Position) -> bool {
+    let (dx, dy) = offset(from, to);
+    let (x, y) = offset(from, position);
+    let steps = dx.abs().max(dy.abs());
+
+    if steps == 0 {
+        return position == from;
+    }
+
+    // The offset after `step` steps along an axis that moves `delta` over the whole line. The
+    // product of two offsets only fits in 128 bits unsigned, so the sign is applied afterwards.
+    let round = |step: i128, delta: i128| {
+        let product = step.unsigned_abs() * delta.unsigned_abs();
+        let (quotient, remainder) = (product / steps as u128, product % steps as u128);
+        let quotient = quotient as i128;
+
+        if delta < 0 {
+            -quotient - i128::from(2 * remainder > steps as u128)
+        } else {
+            quotient + i128::from(2 * remainder >= steps as u128)
+        }
+    };
+
+    let step = if dx.abs() >= dy.abs() {
+        x * dx.signum()
+    } else {
+        y * dy.signum()
+    };
+
+    (0..=steps).contains(&step) && x == round(step, dx) && y == round(step, dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(x: i64, y: i64) -> Position {
+        Position { x, y }
+    }
+
+    fn radius(radius: i64, metric: Metric) -> Area {
+        Area::Radius {
+            center: Anchor::Position(position(0, 0)),
+            radius,
+            metric,
+        }
+    }
+
+    #[test]
+    fn radius_shapes() {
+        let diagonal = position(2, 2);
+
+        assert!(radius(2, Metric::Chebyshev).contains(&diagonal, None));
+        assert!(!radius(3, Metric::Manhattan).contains(&diagonal, None));
+        assert!(radius(4, Metric::Manhattan).contains(&diagonal, None));
+        assert!(!radius(2, Metric::Euclidean).contains(&diagonal, None));
+        assert!(radius(3, Metric::Euclidean).contains(&diagonal, None));
+        assert!(radius(2, Metric::Euclidean).contains(&position(0, -2), None));
+    }
+
+    #[test]
+    fn radius_edge_cases() {
+        let origin = position(0, 0);
+
+        for metric in [Metric::Chebyshev, Metric::Manhattan, Metric::Euclidean] {
+            assert!(radius(0, metric).contains(&origin, None));
+            assert!(!radius(-1, metric).contains(&origin, None));
+            assert!(radius(i64::MAX, metric).contains(&position(i64::MAX, 0), None));
+            assert!(radius(i64::MAX, metric).contains(&position(0, i64::MIN + 1), None));
+            assert!(!radius(i64::MAX, metric).contains(&position(i64::MAX, i64::MIN), None));
+        }
+    }
+
+    #[test]
+    fn source_anchor() {
+        let area = Area::Radius {
+            center: Anchor::Source,
+            radius: 1,
+            metric: Metric::Chebyshev,
+        };
+
+        assert!(area.contains(&position(6, 6), Some(&position(5, 5))));
+        assert!(!area.contains(&position(7, 5), Some(&position(5, 5))));
+        assert!(!area.contains(&position(5, 5), None));
+    }
+
+    #[test]
+    fn rectangle() {
+        let area = Area::Rectangle {
+            corner: position(2, -1),
+            opposite_corner: position(-2, 1),
+        };
+
+        assert!(area.contains(&position(-2, -1), None));
+        assert!(area.contains(&position(2, 1), None));
+        assert!(area.contains(&position(0, 0), None));
+        assert!(!area.contains(&position(3, 0), None));
+        assert!(!area.contains(&position(0, 2), None));
+    }
+
+    #[test]
+    fn line() {
+        let area = Area::Line {
+            from: Anchor::Position(position(0, 0)),
+            to: position(4, 2),
+        };
+
+        let covered: Vec<_> = (-1..=5)
+            .flat_map(|x| (-1..=3).map(move |y| position(x, y)))
+            .filter(|position| area.contains(position, None))
+            .collect();
+
+        assert_eq!(
+            covered,
+            [
+                position(0, 0),
+                position(1, 1),
+                position(2, 1),
+                position(3, 2),
+                position(4, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_position_line() {
+        let area = Area::Line {
+            from: Anchor::Position(position(1, 1)),
+            to: position(1, 1),
+        };
+
+        assert!(area.contains(&position(1, 1), None));
+        assert!(!area.contains(&position(1, 2), None));
+    }
+
+    #[test]
+    fn cone() {
+        let area = Area::Cone {
+            origin: Anchor::Position(position(0, 0)),
+            direction: position(0, 1),
+            range: 3,
+        };
+
+        assert!(!area.contains(&position(0, 0), None));
+        assert!(area.contains(&position(0, 3), None));
+        assert!(area.contains(&position(2, 2), None));
+        assert!(area.contains(&position(-2, 2), None));
+        assert!(!area.contains(&position(3, 2), None));
+        assert!(!area.contains(&position(0, 4), None));
+        assert!(!area.contains(&position(0, -1), None));
+    }
+
+    #[test]
+    fn cone_with_large_coordinates() {
+        let area = Area::Cone {
+            origin: Anchor::Position(position(0, 0)),
+            direction: position(0, 100_000),
+            range: i64::MAX,
+        };
+
+        assert!(area.contains(&position(0, 100_000), None));
+        assert!(area.contains(&position(-(1 << 40), 1 << 40), None));
+        assert!(!area.contains(&position((1 << 40) + 1, 1 << 40), None));
+        assert!(!area.contains(&position(i64::MAX, 0), None));
+
+        let area = Area::Cone {
+            origin: Anchor::Position(position(i64::MIN, i64::MIN)),
+            direction: position(i64::MAX, i64::MAX),
+            range: i64::MAX,
+        };
+
+        assert!(area.contains(&position(i64::MIN + (1 << 62), i64::MIN + (1 << 62)), None));
+        assert!(!area.contains(&position(0, 0), None));
+    }
+
+    #[test]
+    fn line_with_large_coordinates() {
+        let line = |from: Position, to: Position| Area::Line {
+            from: Anchor::Position(from),
+            to,
+        };
+
+        let area = line(position(0, 0), position(i64::MAX, 0));
+        assert!(area.contains(&position(i64::MAX, 0), None));
+        assert!(area.contains(&position(5, 0), None));
+        assert!(!area.contains(&position(5, 1), None));
+        assert!(!area.contains(&position(-1, 0), None));
+
+        let area = line(position(-10, 0), position(i64::MAX, 0));
+        assert!(area.contains(&position(-10, 0), None));
+        assert!(area.contains(&position(i64::MAX, 0), None));
+        assert!(!area.contains(&position(i64::MIN, 0), None));
+
+        let area = line(position(i64::MIN, i64::MIN), position(i64::MAX, 0));
+        assert!(area.contains(&position(i64::MIN + 2, i64::MIN + 1), None));
+        assert!(!area.contains(&position(i64::MIN + 2, i64::MIN + 2), None));
+        assert!(area.contains(&position(i64::MAX, 0), None));
+
+        let area = line(position(i64::MAX, i64::MAX), position(i64::MIN, i64::MIN));
+        assert!(area.contains(&position(0, 0), None));
+        assert!(!area.contains(&position(0, 1), None));
+    }
+}
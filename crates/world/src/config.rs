@@ -0,0 +1,74 @@
+#[derive(Default, Clone, Copy)]
+pub struct WorldConfig {
+    pub budget: ActionBudget,
+    pub tiebreaker: Tiebreaker,
+    pub query_resolution: QueryResolution,
+    pub death_timing: DeathTiming,
+    // Seeds the random number generator used by random effects.
+    pub seed: u64,
+    // Records every top-level action into an `ActionLog` that can be replayed.
+    pub record_actions: bool,
+    // Records the state changed by every top-level action, so it can be undone and redone.
+    pub record_history: bool,
+    // Sends `Notification::StateHash` to the world entity after every top-level action.
+    pub notify_state_hash: bool,
+    // Stops debug builds from printing every action, event and reaction they resolve.
+    pub quiet: bool,
+}
+
+// Limits applied to every top-level action, including all actions spawned by its reactions.
+// Exceeding the budget stops every further reaction that would perform an action, and the remaining
+// targets of a query. Reactions that only change or prevent their event, like Immortal, Ward or
+// Root, still resolve, so entities left without health by changes that already happened are
+// destroyed unless a reaction prevents it.
+#[derive(Clone, Copy)]
+pub struct ActionBudget {
+    pub max_stack_depth: u64,
+    pub max_actions: u64,
+}
+
+impl Default for ActionBudget {
+    fn default() -> Self {
+        Self {
+            max_stack_depth: 64,
+            max_actions: 1024,
+        }
+    }
+}
+
+// Decides the order of reactions with equal priority and reaction priority. Reactions of the same
+// reactor always keep the order they were given in.
+#[derive(Default, Clone, Copy)]
+pub enum Tiebreaker {
+    // Reactors that spawned earlier resolve first, even if they use a higher entity index.
+    #[default]
+    SpawnOrder,
+    // Reactors resolve from the lowest y to the highest, then the lowest x to the highest. Reactors
+    // without a position resolve last, and equal positions fall back to spawn order. Positions
+    // change without reordering subscriptions, so every event with reactions copies and sorts its
+    // subscription list, which costs an allocation per event that spawn order avoids.
+    BoardPosition,
+}
+
+// Decides how `perform_with_query` resolves an action with several targets.
+#[derive(Default, Clone, Copy)]
+pub enum QueryResolution {
+    // The action and all of its reactions resolve on one target before the next target.
+    #[default]
+    Sequential,
+    // The action is applied to every target first. Then the after-events of every target are
+    // emitted in target order, followed by the death phase.
+    Simultaneous,
+}
+
+// Decides when entities without health left are destroyed.
+#[derive(Default, Clone, Copy)]
+pub enum DeathTiming {
+    // When the action that dropped their health completes, after its after-event reactions.
+    #[default]
+    AfterAction,
+    // In a death phase after the top-level action and all of its reactions complete. Until then
+    // they are dying, and random effects do not choose them. Deaths are processed in the order
+    // the entities died, and entities that regained health survive.
+    AfterChain,
+}
@@ -1,45 +1,315 @@
-use crate::{systems::components::*, utils::*, Action, EntityId, Notification, World};
+use crate::{
+    systems::components::*, utils::*, Action, ActionError, ActionOutcome, Area, EntityId, Metric,
+    Notification, QueryResolution, Rng, Target, World,
+};
 
-pub enum ComponentFilter<'qry, Component> {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone)]
+pub enum ComponentFilter<Component> {
+    #[default]
     Ignore,
-    Include(&'qry [Component]),
+    Include(Vec<Component>),
     Any,
 }
 
-pub struct EntityQuery<'qry> {
-    pub allegiance_filter: ComponentFilter<'qry, Allegiance>,
-    pub position_filter: ComponentFilter<'qry, Position>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone)]
+pub enum HealthFilter {
+    #[default]
+    Ignore,
+    Any,
+    // Current health is below the amount.
+    Below(i64),
+    // Current health is below max health.
+    Damaged,
+}
+
+// Relations to the source of the action.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone)]
+pub enum RelationFilter {
+    #[default]
+    Ignore,
+    // Entities with an allegiance other than the source's allegiance.
+    Enemies,
+    // Entities with the source's allegiance, including the source.
+    Allies,
+    NotSource,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub enum EntityQuery {
+    Filter(EntityFilter),
+    // Entities matched by every query. Matches every entity if there are no queries.
+    And(Vec<EntityQuery>),
+    // Entities matched by any query.
+    Or(Vec<EntityQuery>),
+    // Entities matched by the first query but not the second.
+    Sub(Box<EntityQuery>, Box<EntityQuery>),
+    // Entities not matched by the query.
+    Not(Box<EntityQuery>),
+    // The entities matched by the query, ordered, and then limited to the first entities. The
+    // action is performed on them in this order.
+    Select {
+        query: Box<EntityQuery>,
+        order: Order,
+        // Reverses the order. Entities that lack what the order depends on still come last.
+        descending: bool,
+        limit: Option<usize>,
+    },
+}
+
+// Entities that lack the component or position an order depends on come last. Ties keep spawn
+// order.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy)]
+pub enum Order {
+    // Earliest spawned first, like `Tiebreaker::SpawnOrder`.
+    SpawnOrder,
+    // Nearest to the source first.
+    Distance(Metric),
+    // Lowest current health first.
+    Health,
+    // Lowest armor first.
+    Armor,
+    // Shuffled with the world's random number generator.
+    Random,
+}
+
+impl From<EntityFilter> for EntityQuery {
+    fn from(filter: EntityFilter) -> Self {
+        Self::Filter(filter)
+    }
+}
+
+// Matches the entities that pass every filter. A filter that ignores everything matches every
+// entity.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Default, Clone)]
+pub struct EntityFilter {
+    pub allegiance_filter: ComponentFilter<Allegiance>,
+    pub armor_filter: ComponentFilter<Armor>,
+    pub health_filter: HealthFilter,
+    pub position_filter: ComponentFilter<Position>,
+    // Includes entities with a position inside the area.
+    pub area_filter: Option<Area>,
+    // Includes entities with at least one reaction of the given kinds.
+    pub reaction_filter: ComponentFilter<ReactionKind>,
+    pub relation_filter: RelationFilter,
 }
 
 impl<NotificationHandler> World<NotificationHandler>
 where
     NotificationHandler: Fn(EntityId, Notification),
 {
-    fn entities(&self, query: EntityQuery) -> EntitySet {
-        let allegiance_entities = match query.allegiance_filter {
+    // Returns the entities matched by the query, in the order the action is performed on them.
+    pub(crate) fn targets(
+        &self,
+        query: &EntityQuery,
+        source: EntityId,
+        rng: &mut Rng,
+    ) -> Vec<EntityId> {
+        let spawn_order = |entities: &EntitySet| {
+            let mut entities: Vec<_> = self.registry.iter(entities).collect();
+            entities.sort_by_key(|entity| self.registry.spawn_sequence(entity));
+            entities
+        };
+
+        let EntityQuery::Select {
+            query,
+            order,
+            descending,
+            limit,
+        } = query
+        else {
+            return spawn_order(&self.entities(query, source, rng));
+        };
+
+        let mut targets = spawn_order(&self.entities(query, source, rng));
+        let source_position = self.position_system.position(&source);
+
+        let key = |entity: &EntityId| match order {
+            Order::SpawnOrder | Order::Random => Some(self.registry.spawn_sequence(entity).into()),
+            Order::Distance(metric) => source_position
+                .zip(self.position_system.position(entity))
+                .map(|(source_position, position)| metric.distance_key(source_position, position)),
+            Order::Health => self
+                .health_system
+                .health(entity)
+                .map(|health| health.current.into()),
+            Order::Armor => self
+                .armor_system
+                .armor(entity)
+                .map(|armor| armor.current.into()),
+        };
+
+        targets.sort_by(|a, b| match (key(a), key(b)) {
+            (Some(a), Some(b)) if *descending => b.cmp(&a),
+            (Some(a), Some(b)) => a.cmp(&b),
+            (a, b) => a.is_none().cmp(&b.is_none()),
+        });
+
+        if let Order::Random = order {
+            for index in (1..targets.len()).rev() {
+                targets.swap(index, rng.below(index as u64 + 1) as usize);
+            }
+        }
+
+        if let Some(limit) = limit {
+            targets.truncate(*limit);
+        }
+
+        targets
+    }
+
+    pub(crate) fn entities(
+        &self,
+        query: &EntityQuery,
+        source: EntityId,
+        rng: &mut Rng,
+    ) -> EntitySet {
+        match query {
+            EntityQuery::Filter(filter) => self.filtered_entities(filter, source),
+            EntityQuery::And(queries) => {
+                if queries.is_empty() {
+                    return self.registry.alive().clone();
+                }
+
+                let entities: Vec<_> = queries
+                    .iter()
+                    .map(|query| self.entities(query, source, rng))
+                    .collect();
+
+                EntitySet::intersection(&entities.iter().map(Some).collect::<Vec<_>>())
+            }
+            EntityQuery::Or(queries) => {
+                let entities: Vec<_> = queries
+                    .iter()
+                    .map(|query| self.entities(query, source, rng))
+                    .collect();
+
+                EntitySet::union(entities.iter())
+            }
+            EntityQuery::Sub(query, excluded_query) => {
+                let entities = self.entities(query, source, rng);
+                entities.difference(&self.entities(excluded_query, source, rng))
+            }
+            EntityQuery::Not(query) => self
+                .registry
+                .alive()
+                .difference(&self.entities(query, source, rng)),
+            EntityQuery::Select { .. } => self.targets(query, source, rng).into_iter().collect(),
+        }
+    }
+
+    fn filtered_entities(&self, filter: &EntityFilter, source: EntityId) -> EntitySet {
+        let allegiance_entities = match &filter.allegiance_filter {
+            ComponentFilter::Ignore => None,
             ComponentFilter::Include(allegiances) => {
                 Some(self.allegiance_system.entities(allegiances))
             }
-            _ => None,
+            ComponentFilter::Any => Some(self.allegiance_system.entities.clone()),
+        };
+
+        let armor_entities = match &filter.armor_filter {
+            ComponentFilter::Ignore => None,
+            ComponentFilter::Include(armors) => {
+                Some(self.registry.filter(&self.armor_system.entities, |entity| {
+                    self.armor_system
+                        .armor(entity)
+                        .is_some_and(|armor| armors.contains(armor))
+                }))
+            }
+            ComponentFilter::Any => Some(self.armor_system.entities.clone()),
+        };
+
+        let health_entities = match &filter.health_filter {
+            HealthFilter::Ignore => None,
+            HealthFilter::Any => Some(self.health_system.entities.clone()),
+            HealthFilter::Below(amount) => Some(self.registry.filter(
+                &self.health_system.entities,
+                |entity| {
+                    self.health_system
+                        .health(entity)
+                        .is_some_and(|health| health.current < *amount)
+                },
+            )),
+            HealthFilter::Damaged => Some(self.registry.filter(
+                &self.health_system.entities,
+                |entity| {
+                    self.health_system
+                        .health(entity)
+                        .is_some_and(|health| health.current < health.max)
+                },
+            )),
         };
 
-        let position_entities = match query.position_filter {
+        let position_entities = match &filter.position_filter {
+            ComponentFilter::Ignore => None,
             ComponentFilter::Include(positions) => Some(self.position_system.entities(positions)),
-            _ => None,
-        };
-
-        EntitySet::intersection(&[
-            match query.allegiance_filter {
-                ComponentFilter::Ignore => None,
-                ComponentFilter::Include(_) => allegiance_entities.as_ref(),
-                ComponentFilter::Any => Some(&self.allegiance_system.entities),
-            },
-            match query.position_filter {
-                ComponentFilter::Ignore => None,
-                ComponentFilter::Include(_) => position_entities.as_ref(),
-                ComponentFilter::Any => Some(&self.position_system.entities),
-            },
-        ])
+            ComponentFilter::Any => Some(self.position_system.entities.clone()),
+        };
+
+        let area_entities = filter.area_filter.as_ref().map(|area| {
+            let source_position = self.position_system.position(&source);
+
+            self.position_system
+                .entities_where(|position| area.contains(position, source_position))
+        });
+
+        let reaction_entities = match &filter.reaction_filter {
+            ComponentFilter::Ignore => None,
+            ComponentFilter::Include(kinds) => Some(self.registry.filter(
+                &self.reaction_system.entities,
+                |entity| {
+                    self.reaction_system
+                        .reactions(entity)
+                        .is_some_and(|reactions| {
+                            reactions
+                                .iter()
+                                .any(|reaction| kinds.contains(&reaction.kind()))
+                        })
+                },
+            )),
+            ComponentFilter::Any => Some(self.reaction_system.entities.clone()),
+        };
+
+        let source_allegiance = self.allegiance_system.allegiance(&source);
+
+        let relation_entities = match &filter.relation_filter {
+            RelationFilter::Ignore => None,
+            RelationFilter::Enemies => Some(match source_allegiance {
+                Some(allegiance) => self
+                    .allegiance_system
+                    .entities
+                    .difference(&self.allegiance_system.entities(&[*allegiance])),
+                None => Default::default(),
+            }),
+            RelationFilter::Allies => Some(match source_allegiance {
+                Some(allegiance) => self.allegiance_system.entities(&[*allegiance]),
+                None => Default::default(),
+            }),
+            RelationFilter::NotSource => {
+                Some(self.registry.alive().difference(&EntitySet::from([source])))
+            }
+        };
+
+        let filtered_entities = [
+            allegiance_entities,
+            armor_entities,
+            health_entities,
+            position_entities,
+            area_entities,
+            reaction_entities,
+            relation_entities,
+        ];
+
+        if filtered_entities.iter().all(Option::is_none) {
+            return self.registry.alive().clone();
+        }
+
+        EntitySet::intersection(&filtered_entities.each_ref().map(Option::as_ref))
     }
 
     pub fn perform_with_query(
@@ -47,10 +317,40 @@ where
         action: Action,
         source: EntityId,
         query: EntityQuery,
-        stack_depth: u64,
-    ) {
-        for target in self.entities(query).iter() {
-            self.perform(action.clone(), source, target, stack_depth)
+    ) -> Result<ActionOutcome, ActionError> {
+        self.start_chain();
+
+        // Random orders draw from a copy of the generator, since the query borrows the world.
+        let mut rng = self.rng;
+        let targets = self.targets(&query, source, &mut rng);
+        self.rng = rng;
+
+        self.log_action(action.clone(), source, Target::Query(query));
+
+        if let Err(error) = self.check_source(&source) {
+            return self.finish_chain(Err(error));
+        }
+
+        if let QueryResolution::Simultaneous = self.config.query_resolution {
+            self.chain.deferred_events = Some(Vec::new());
         }
+
+        for target in targets {
+            if self.chain.aborted.is_some() {
+                break;
+            }
+
+            let _ = self.execute(action.clone(), source, target, 0);
+        }
+
+        for (mut event, source, target) in self.chain.deferred_events.take().unwrap_or_default() {
+            if self.registry.contains(&target) {
+                self.emit(&mut event, source, target, 0);
+            }
+        }
+
+        self.process_deaths();
+
+        self.finish_chain(Ok(()))
     }
 }
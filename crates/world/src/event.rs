@@ -1,10 +1,69 @@
-use crate::{log_with_indentation, systems::components::*, Action, EntityId, Notification, World};
+use std::{cmp::Reverse, rc::Rc};
 
-#[cfg_attr(debug_assertions, derive(Debug))]
+use crate::{
+    log_with_indentation, systems::components::*, Action, EntityId, EntitySet, Notification,
+    Tiebreaker, World,
+};
+
+#[derive(Debug)]
 pub enum Event {
-    AfterMove { from_position: Position },
+    // Before-events are emitted before an action is applied. Reactions may modify them to change
+    // or prevent the pending action.
+    BeforeMove {
+        to_position: Position,
+        prevented: bool,
+    },
+    AfterMove {
+        from_position: Position,
+    },
+    BeforeDamage {
+        amount: i64,
+    },
+    AfterDamage,
+    AfterHeal {
+        amount: i64,
+    },
+    BeforeDestroy {
+        prevented: bool,
+    },
+    AfterChangeAllegiance {
+        from_allegiance: Allegiance,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    BeforeMove,
+    AfterMove,
+    BeforeDamage,
     AfterDamage,
+    AfterHeal,
     BeforeDestroy,
+    AfterChangeAllegiance,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::BeforeMove { .. } => EventKind::BeforeMove,
+            Event::AfterMove { .. } => EventKind::AfterMove,
+            Event::BeforeDamage { .. } => EventKind::BeforeDamage,
+            Event::AfterDamage => EventKind::AfterDamage,
+            Event::AfterHeal { .. } => EventKind::AfterHeal,
+            Event::BeforeDestroy { .. } => EventKind::BeforeDestroy,
+            Event::AfterChangeAllegiance { .. } => EventKind::AfterChangeAllegiance,
+        }
+    }
+
+    // Returns whether a reaction prevented the pending action.
+    pub fn is_prevented(&self) -> bool {
+        match self {
+            Event::BeforeMove { prevented, .. } => *prevented,
+            Event::BeforeDamage { amount } => *amount <= 0,
+            Event::BeforeDestroy { prevented } => *prevented,
+            _ => false,
+        }
+    }
 }
 
 impl<NotificationHandler> World<NotificationHandler>
@@ -13,14 +72,22 @@ where
 {
     fn handle_event(
         &mut self,
-        event: &Event,
+        event: &mut Event,
         source: EntityId,
         target: EntityId,
         reactor: EntityId,
         reaction: &Reaction,
         stack_depth: u64,
     ) {
-        match (event, reaction) {
+        let reaction_action = match (event, reaction) {
+            (Event::BeforeMove { prevented, .. }, Reaction::Root) => {
+                if !(target == reactor) {
+                    return;
+                }
+
+                *prevented = true;
+                None
+            }
             (Event::AfterMove { from_position }, Reaction::OpportunityAttack { damage_amount }) => {
                 let Some(reactor_allegiance) = self.allegiance_system.allegiance(&reactor) else {
                     return;
@@ -42,62 +109,240 @@ where
                     return;
                 }
 
-                log_with_indentation!(stack_depth, "[Reaction] {reactor:?} {reaction:?}");
-
-                self.perform(
+                Some((
                     Action::Damage {
                         amount: *damage_amount,
                     },
                     reactor,
                     target,
-                    stack_depth + 1,
-                )
+                ))
+            }
+            (Event::BeforeDamage { amount }, Reaction::Ward { damage_reduction }) => {
+                if !(target == reactor) {
+                    return;
+                }
+
+                *amount = (*amount - damage_reduction).max(0);
+                None
+            }
+            (Event::BeforeDamage { amount }, Reaction::DivineShield) => {
+                if !(target == reactor) {
+                    return;
+                }
+
+                *amount = 0;
+
+                Some((
+                    Action::RemoveReaction {
+                        reaction: Reaction::DivineShield,
+                    },
+                    reactor,
+                    reactor,
+                ))
             }
             (Event::AfterDamage, Reaction::Reinforce { armor_amount }) => {
                 if !(target == reactor) {
                     return;
                 }
 
-                log_with_indentation!(stack_depth, "[Reaction] {reactor:?} {reaction:?}");
+                Some((
+                    Action::GainArmor {
+                        amount: *armor_amount,
+                    },
+                    reactor,
+                    reactor,
+                ))
+            }
+            (Event::AfterHeal { .. }, Reaction::Empathy { armor_amount }) => {
+                let Some(reactor_allegiance) = self.allegiance_system.allegiance(&reactor) else {
+                    return;
+                };
+
+                let Some(target_allegiance) = self.allegiance_system.allegiance(&target) else {
+                    return;
+                };
+
+                if !(target_allegiance == reactor_allegiance) {
+                    return;
+                }
 
-                self.perform(
+                Some((
                     Action::GainArmor {
                         amount: *armor_amount,
                     },
                     reactor,
                     reactor,
-                    stack_depth + 1,
-                )
+                ))
             }
-            (Event::BeforeDestroy, Reaction::Spite { damage_amount }) => {
+            (Event::BeforeDestroy { prevented }, Reaction::Immortal) => {
                 if !(target == reactor) {
                     return;
                 }
 
-                log_with_indentation!(stack_depth, "[Reaction] {reactor:?} {reaction:?}");
+                *prevented = true;
+                None
+            }
+            (Event::BeforeDestroy { .. }, Reaction::Spite { damage_amount }) => {
+                if !(target == reactor) {
+                    return;
+                }
 
-                self.perform(
+                Some((
                     Action::Damage {
                         amount: *damage_amount,
                     },
                     reactor,
                     source,
-                    stack_depth + 1,
-                )
+                ))
             }
-            _ => (),
+            (Event::BeforeDestroy { .. }, Reaction::Volatile { damage_amount }) => {
+                if !(target == reactor) {
+                    return;
+                }
+
+                let Some(reactor_allegiance) = self.allegiance_system.allegiance(&reactor) else {
+                    return;
+                };
+
+                let enemies = EntitySet::intersection(&[
+                    Some(&self.allegiance_system.entities),
+                    Some(&self.health_system.entities),
+                ])
+                .difference(&self.allegiance_system.entities(&[*reactor_allegiance]))
+                .difference(&self.chain.destroying)
+                .difference(&self.chain.dying_entities);
+
+                let Some(enemy) = self.registry.choose(&enemies, &mut self.rng) else {
+                    return;
+                };
+
+                Some((
+                    Action::Damage {
+                        amount: *damage_amount,
+                    },
+                    reactor,
+                    enemy,
+                ))
+            }
+            (
+                Event::AfterChangeAllegiance { from_allegiance },
+                Reaction::Loyalty { damage_amount },
+            ) => {
+                let Some(reactor_allegiance) = self.allegiance_system.allegiance(&reactor) else {
+                    return;
+                };
+
+                if !(from_allegiance == reactor_allegiance) {
+                    return;
+                }
+
+                let Some(target_allegiance) = self.allegiance_system.allegiance(&target) else {
+                    return;
+                };
+
+                if !(target_allegiance != reactor_allegiance) {
+                    return;
+                }
+
+                Some((
+                    Action::Damage {
+                        amount: *damage_amount,
+                    },
+                    reactor,
+                    target,
+                ))
+            }
+            _ => return,
+        };
+
+        let Some((action, action_source, action_target)) = reaction_action else {
+            log_with_indentation!(self, stack_depth, "[Reaction] {reactor:?} {reaction:?}");
+            return;
+        };
+
+        // Once the chain is aborted, reactions that perform actions are refused, while reactions
+        // that only change or prevent their event still resolve.
+        if self.chain.aborted.is_some() {
+            log_with_indentation!(self, stack_depth, "[Aborted] {reactor:?} {reaction:?}");
+            return;
+        }
+
+        let budget = self.config.budget;
+
+        if stack_depth + 1 > budget.max_stack_depth
+            || self.chain.actions_performed >= budget.max_actions
+        {
+            log_with_indentation!(self, stack_depth, "[Aborted] {reactor:?} {reaction:?}");
+
+            self.chain.aborted = Some((reactor, *reaction));
+
+            return;
         }
+
+        log_with_indentation!(self, stack_depth, "[Reaction] {reactor:?} {reaction:?}");
+
+        let _ = self.execute(action, action_source, action_target, stack_depth + 1);
     }
 
-    pub fn emit(&mut self, event: &Event, source: EntityId, target: EntityId, stack_depth: u64) {
-        log_with_indentation!(stack_depth, "[Event] {source:?} -> {target:?} {event:?}");
+    pub fn emit(
+        &mut self,
+        event: &mut Event,
+        source: EntityId,
+        target: EntityId,
+        stack_depth: u64,
+    ) {
+        let Some(subscriptions) = self.reaction_system.subscriptions(event.kind()) else {
+            return;
+        };
+
+        if subscriptions.is_empty() {
+            return;
+        }
+
+        log_with_indentation!(
+            self,
+            stack_depth,
+            "[Event] {source:?} -> {target:?} {event:?}"
+        );
 
-        let reactions_map = self.reaction_system.reactions_map.clone();
+        // Subscriptions are already ordered by descending priority, then reaction priority, then
+        // spawn order. Ordering by board position depends on the current positions, so it sorts a
+        // copy instead.
+        let subscriptions = match self.config.tiebreaker {
+            Tiebreaker::SpawnOrder => subscriptions,
+            Tiebreaker::BoardPosition => {
+                let mut subscriptions = subscriptions.to_vec();
 
-        for (reactor, reactions) in reactions_map.into_iter() {
-            for reaction in reactions {
-                self.handle_event(event, source, target, reactor, &reaction, stack_depth)
+                subscriptions.sort_by_key(|(reactor, reaction)| {
+                    let board_position = self
+                        .position_system
+                        .position(reactor)
+                        .map(|position| (position.y, position.x));
+
+                    (
+                        Reverse(reaction.priority()),
+                        Reverse(self.reaction_system.reaction_priority(reactor)),
+                        board_position.is_none(),
+                        board_position,
+                        self.registry.spawn_sequence(reactor),
+                    )
+                });
+
+                Rc::new(subscriptions)
+            }
+        };
+
+        for (reactor, reaction) in subscriptions.iter() {
+            if event.is_prevented() {
+                return;
             }
+
+            // Reactors destroyed by earlier reactions to the event do not react.
+            if !self.registry.contains(reactor) {
+                continue;
+            }
+
+            self.handle_event(event, source, target, *reactor, reaction, stack_depth)
         }
     }
 }
@@ -0,0 +1,316 @@
+use crate::{EntityId, EntitySnapshot, LoggedAction, Notification, Rng, World, WORLD_ENTITY};
+
+// The top-level actions that can be undone, and the undone actions that can be redone, in the
+// order they were performed.
+#[derive(Default, Clone)]
+pub(crate) struct History {
+    undo: Vec<Revision>,
+    redo: Vec<Revision>,
+}
+
+// The state of every entity index a top-level action touched, before and after the action.
+#[derive(Clone)]
+pub(crate) struct Revision {
+    before: RevisionState,
+    after: RevisionState,
+    // The length of the action log before the action. Undoing takes the actions logged since off
+    // the log, and redoing puts them back.
+    logged_actions: usize,
+    undone_actions: Vec<LoggedAction>,
+}
+
+#[derive(Clone, PartialEq)]
+struct RevisionState {
+    generations_len: usize,
+    next_spawn_sequence: u64,
+    rng: Rng,
+    indices: Vec<IndexState>,
+}
+
+#[derive(Clone, PartialEq)]
+struct IndexState {
+    index: usize,
+    // None if the index was not used yet.
+    generation: Option<u32>,
+    spawn_sequence: u64,
+    // The entity alive at the index.
+    entity: Option<EntitySnapshot>,
+}
+
+impl<NotificationHandler> World<NotificationHandler>
+where
+    NotificationHandler: Fn(EntityId, Notification),
+{
+    // Reverts the state changed by the last top-level action that was not undone yet, and notifies
+    // the changes. Returns false if there is nothing to undo, or history is not recorded.
+    pub fn undo(&mut self) -> bool {
+        let Some(mut revision) = self.history.as_mut().and_then(|history| history.undo.pop())
+        else {
+            return false;
+        };
+
+        if let Some(log) = &mut self.action_log {
+            revision.undone_actions = log.actions.split_off(revision.logged_actions);
+        }
+
+        self.apply_revision_state(&revision.before);
+
+        if let Some(history) = &mut self.history {
+            history.redo.push(revision);
+        }
+
+        true
+    }
+
+    // Restores the state recorded after the last undone action, and notifies the changes. The action
+    // is not performed again, so no reactions resolve and no random numbers are drawn. Returns false
+    // if there is nothing to redo, including after another action was performed.
+    pub fn redo(&mut self) -> bool {
+        let Some(mut revision) = self.history.as_mut().and_then(|history| history.redo.pop())
+        else {
+            return false;
+        };
+
+        if let Some(log) = &mut self.action_log {
+            log.actions.append(&mut revision.undone_actions);
+        }
+
+        self.apply_revision_state(&revision.after);
+
+        if let Some(history) = &mut self.history {
+            history.undo.push(revision);
+        }
+
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history
+            .as_ref()
+            .is_some_and(|history| !history.undo.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history
+            .as_ref()
+            .is_some_and(|history| !history.redo.is_empty())
+    }
+
+    pub(crate) fn start_revision(&self) -> Option<Revision> {
+        self.history.as_ref()?;
+
+        Some(Revision {
+            before: RevisionState {
+                generations_len: self.registry.generations().len(),
+                next_spawn_sequence: self.registry.next_spawn_sequence(),
+                rng: self.rng,
+                indices: Vec::new(),
+            },
+            after: RevisionState {
+                generations_len: 0,
+                next_spawn_sequence: 0,
+                rng: self.rng,
+                indices: Vec::new(),
+            },
+            logged_actions: self.action_log.as_ref().map_or(0, |log| log.actions.len()),
+            undone_actions: Vec::new(),
+        })
+    }
+
+    // Captures the state of the index the first time the chain touches it.
+    pub(crate) fn touch(&mut self, index: usize) {
+        // The world entity has no state.
+        if index == WORLD_ENTITY.index || self.chain.touched.contains_index(index) {
+            return;
+        }
+
+        if self.chain.revision.is_none() {
+            return;
+        }
+
+        let state = self.index_state(index);
+        self.chain.touched.insert_index(index);
+
+        if let Some(revision) = &mut self.chain.revision {
+            revision.before.indices.push(state);
+        }
+    }
+
+    // Adds the revision to the history, unless the action changed nothing. Performing an action
+    // discards the undone actions.
+    pub(crate) fn finish_revision(&mut self, mut revision: Revision) {
+        revision.after = RevisionState {
+            generations_len: self.registry.generations().len(),
+            next_spawn_sequence: self.registry.next_spawn_sequence(),
+            rng: self.rng,
+            indices: revision
+                .before
+                .indices
+                .iter()
+                .map(|state| self.index_state(state.index))
+                .collect(),
+        };
+
+        if revision.before == revision.after {
+            return;
+        }
+
+        if let Some(history) = &mut self.history {
+            history.undo.push(revision);
+            history.redo.clear();
+        }
+    }
+
+    fn index_state(&self, index: usize) -> IndexState {
+        let generation = self.registry.generations().get(index).copied();
+        let spawn_sequence = self
+            .registry
+            .spawn_sequences()
+            .get(index)
+            .copied()
+            .unwrap_or_default();
+
+        let entity = generation
+            .map(|_| self.registry.resolve(index))
+            .filter(|entity| self.registry.contains(entity))
+            .map(|entity| self.entity_snapshot(entity));
+
+        IndexState {
+            index,
+            generation,
+            spawn_sequence,
+            entity,
+        }
+    }
+
+    // Puts the touched indices back into the state, along with the random number generator. The
+    // entities are rebuilt silently, then their changes are notified as a whole.
+    fn apply_revision_state(&mut self, state: &RevisionState) {
+        let nested = self.notification_handler.muted();
+        self.notification_handler.set_muted(true);
+
+        let mut from: Vec<_> = state
+            .indices
+            .iter()
+            .filter_map(|index_state| self.index_state(index_state.index).entity)
+            .collect();
+
+        for entity in &from {
+            self.remove_entity(entity.id);
+        }
+
+        self.registry
+            .resize(state.generations_len, state.next_spawn_sequence);
+
+        for index_state in &state.indices {
+            if let Some(generation) = index_state.generation {
+                self.registry.set(
+                    index_state.index,
+                    generation,
+                    index_state.spawn_sequence,
+                    index_state.entity.is_some(),
+                );
+            }
+        }
+
+        self.rng = state.rng;
+
+        let mut to: Vec<_> = state
+            .indices
+            .iter()
+            .filter_map(|index_state| index_state.entity.clone())
+            .collect();
+
+        for entity in &to {
+            self.insert_entity(entity.clone());
+        }
+
+        if !nested {
+            self.notification_handler.set_muted(false);
+
+            from.sort_by_key(|entity| entity.id.index);
+            to.sort_by_key(|entity| entity.id.index);
+            self.notify_changes(&from, &to);
+
+            if self.config.notify_state_hash {
+                self.notification_handler
+                    .notify(WORLD_ENTITY, Notification::StateHash(self.state_hash()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn spawn(world: &mut World<impl Fn(EntityId, Notification)>) -> EntityId {
+        world
+            .perform(
+                Action::Spawn {
+                    allegiance: None,
+                    armor: Some(Armor { current: 1 }),
+                    health: Some(Health { current: 5, max: 5 }),
+                    position: None,
+                    reactions: vec![Reaction::Reinforce { armor_amount: 1 }],
+                    reaction_priority: 0,
+                },
+                WORLD_ENTITY,
+                WORLD_ENTITY,
+            )
+            .unwrap()
+            .spawned()
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_state_hash() {
+        let config = WorldConfig {
+            record_actions: true,
+            record_history: true,
+            quiet: true,
+            ..Default::default()
+        };
+
+        let mut world = World::with_config(|_, _| {}, config);
+        let empty = world.state_hash();
+
+        let entity = spawn(&mut world);
+        let spawned = world.state_hash();
+
+        world
+            .perform(Action::Damage { amount: 3 }, WORLD_ENTITY, entity)
+            .unwrap();
+        world.perform(Action::Destroy, entity, entity).unwrap();
+        let destroyed = world.state_hash();
+
+        assert!(world.undo());
+        assert!(world.undo());
+        assert_eq!(world.state_hash(), spawned);
+        assert!(world.undo());
+        assert_eq!(world.state_hash(), empty);
+        assert!(!world.undo());
+        assert!(world.verify_replay());
+
+        assert!(world.redo());
+        assert_eq!(world.state_hash(), spawned);
+        assert!(world.redo());
+        assert!(world.redo());
+        assert_eq!(world.state_hash(), destroyed);
+        assert!(!world.redo());
+        assert!(world.verify_replay());
+
+        // Undoing the destroy brings the entity back under the same id.
+        assert!(world.undo());
+        assert_eq!(world.lifecycle(&entity), Some(Lifecycle::Alive));
+        assert!(world.verify_replay());
+
+        // Performing another action discards the undone one.
+        world
+            .perform(Action::Heal { amount: 1 }, WORLD_ENTITY, entity)
+            .unwrap();
+        assert!(!world.can_redo());
+        assert!(world.verify_replay());
+    }
+}
@@ -1,24 +1,52 @@
 mod action;
+mod area;
+mod config;
 mod entity_query;
 mod event;
+mod history;
+mod lifecycle;
 mod notification;
+mod outcome;
+mod registry;
+mod replay;
+mod rng;
+mod snapshot;
 mod systems;
+mod transaction;
 mod utils;
 
+use action::Chain;
 use event::*;
+use history::*;
+use registry::*;
 use systems::*;
 use utils::*;
 
 pub use action::Action;
+pub use area::*;
+pub use config::*;
 pub use entity_query::*;
+pub use lifecycle::*;
 pub use notification::*;
+pub use outcome::*;
+pub use replay::*;
+pub use rng::*;
+pub use snapshot::*;
 pub use systems::components::*;
 
-pub const WORLD_ENTITY: EntityId = EntityId(0);
+pub const WORLD_ENTITY: EntityId = EntityId {
+    index: 0,
+    generation: 0,
+};
 
 pub struct World<NotificationHandler> {
-    notification_handler: NotificationHandler,
-    next_entity: EntityId,
+    notification_handler: Notifier<NotificationHandler>,
+    config: WorldConfig,
+    registry: Registry,
+    chain: Chain,
+    rng: Rng,
+    action_log: Option<ActionLog>,
+    history: Option<History>,
 
     allegiance_system: AllegianceSystem<NotificationHandler>,
     armor_system: ArmorSystem<NotificationHandler>,
@@ -32,25 +60,61 @@ where
     NotificationHandler: Fn(EntityId, Notification) + Clone,
 {
     pub fn new(notification_handler: NotificationHandler) -> Self {
-        Self {
+        Self::with_config(notification_handler, Default::default())
+    }
+
+    pub fn with_config(notification_handler: NotificationHandler, config: WorldConfig) -> Self {
+        let notification_handler = Notifier::new(notification_handler);
+
+        let mut world = Self {
             notification_handler: notification_handler.clone(),
-            next_entity: EntityId(1),
+            config,
+            registry: Default::default(),
+            chain: Default::default(),
+            rng: Rng::new(config.seed),
+            action_log: None,
+            history: config.record_history.then(Default::default),
             allegiance_system: AllegianceSystem::new(notification_handler.clone()),
             armor_system: ArmorSystem::new(notification_handler.clone()),
             health_system: HealthSystem::new(notification_handler.clone()),
             position_system: PositionSystem::new(notification_handler.clone()),
             reaction_system: ReactionSystem::new(notification_handler),
+        };
+
+        if config.record_actions {
+            world.action_log = Some(ActionLog {
+                start: world.snapshot(),
+                actions: Vec::new(),
+            });
         }
+
+        world
     }
 }
 
-#[derive(Default, Clone, Copy, PartialEq, Eq, Hash)]
-#[repr(transparent)]
-pub struct EntityId(pub usize);
+// The index identifies the entity among the entities alive at the same time. The generation tells
+// apart the entities that used the same index, so ids of destroyed entities are never mistaken for
+// the entity that reused their index.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub struct EntityId {
+    pub index: usize,
+    pub generation: u32,
+}
+
+// Entity maps use an identity hasher, which only hashes the index. Entities with the same index
+// are still told apart by their generation when compared.
+impl std::hash::Hash for EntityId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_usize(self.index)
+    }
+}
 
-#[cfg(debug_assertions)]
 impl std::fmt::Debug for EntityId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        match self.generation {
+            0 => self.index.fmt(f),
+            generation => f.write_fmt(format_args!("{}v{generation}", self.index)),
+        }
     }
 }
@@ -0,0 +1,38 @@
+use crate::{EntityId, Notification, World, WORLD_ENTITY};
+
+// Entities are alive from the moment they spawn. They are dying while they wait for the death
+// phase, or while their BeforeDestroy reactions resolve, and destroyed once they are removed from
+// the world. Destroyed entities refuse every action, and do not react to events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    Alive,
+    Dying,
+    Destroyed,
+}
+
+impl<NotificationHandler> World<NotificationHandler>
+where
+    NotificationHandler: Fn(EntityId, Notification),
+{
+    // Returns None for entities that were never spawned. The world entity is always alive, although
+    // actions only accept it as their source.
+    pub fn lifecycle(&self, entity: &EntityId) -> Option<Lifecycle> {
+        if *entity == WORLD_ENTITY {
+            return Some(Lifecycle::Alive);
+        }
+
+        if !self.registry.spawned(entity) {
+            return None;
+        }
+
+        if !self.registry.contains(entity) {
+            return Some(Lifecycle::Destroyed);
+        }
+
+        if self.chain.destroying.contains(entity) || self.chain.dying_entities.contains(entity) {
+            return Some(Lifecycle::Dying);
+        }
+
+        Some(Lifecycle::Alive)
+    }
+}
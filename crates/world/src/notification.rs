@@ -1,4 +1,6 @@
-use crate::{Allegiance, Armor, Health, Position, Reaction};
+use std::{cell::Cell, rc::Rc};
+
+use crate::{Allegiance, Armor, EntityId, Health, Position, Reaction};
 
 pub enum Notification<'a> {
     Spawn {
@@ -14,4 +16,51 @@ pub enum Notification<'a> {
     ChangeHealth(Option<&'a Health>),
     ChangePosition(Option<&'a Position>),
     ChangeReactions(&'a Vec<Reaction>),
+    // The `World::state_hash` after a top-level action, sent to the world entity.
+    StateHash(u64),
+}
+
+// Passes notifications on to the handler. The world and its systems share the muted flag, which
+// holds notifications back while a transaction is open.
+pub(crate) struct Notifier<NotificationHandler> {
+    notification_handler: NotificationHandler,
+    muted: Rc<Cell<bool>>,
+}
+
+impl<NotificationHandler> Clone for Notifier<NotificationHandler>
+where
+    NotificationHandler: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            notification_handler: self.notification_handler.clone(),
+            muted: self.muted.clone(),
+        }
+    }
+}
+
+impl<NotificationHandler> Notifier<NotificationHandler>
+where
+    NotificationHandler: Fn(EntityId, Notification),
+{
+    pub fn new(notification_handler: NotificationHandler) -> Self {
+        Self {
+            notification_handler,
+            muted: Default::default(),
+        }
+    }
+
+    pub fn notify(&self, entity: EntityId, notification: Notification) {
+        if !self.muted.get() {
+            (self.notification_handler)(entity, notification);
+        }
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted.get()
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.set(muted);
+    }
 }
@@ -0,0 +1,95 @@
+use crate::{Allegiance, Armor, EntityId, Health, Position, Reaction};
+
+#[derive(Debug, Default, Clone)]
+pub struct ActionOutcome {
+    pub actions_performed: u64,
+    // Every state change made by the action and its reactions, in the order they happened.
+    pub changes: Vec<(EntityId, Change)>,
+}
+
+impl ActionOutcome {
+    // Returns every entity spawned by the action and its reactions, in spawn order.
+    pub fn spawned(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.changes
+            .iter()
+            .filter_map(|(entity, change)| match change {
+                Change::Spawn => Some(*entity),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Change {
+    Spawn,
+    Destroy,
+    Allegiance {
+        from: Allegiance,
+        to: Allegiance,
+    },
+    Armor {
+        from: Armor,
+        to: Armor,
+    },
+    Health {
+        from: Health,
+        to: Health,
+    },
+    Position {
+        from: Position,
+        to: Position,
+    },
+    Reactions {
+        from: Vec<Reaction>,
+        to: Vec<Reaction>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum ActionError {
+    // The source was never spawned, or its spawn was undone.
+    SourceMissing,
+    TargetMissing,
+    ComponentMissing(ComponentKind),
+    EntityDestroyed,
+    // Every entity index that entity sets can hold is in use. See the `bitset-256` feature.
+    EntityLimitReached,
+    // The chain was aborted at the reaction that would have performed the next action. Changes made
+    // before the abort are kept, including the deaths they caused, and listed in the outcome. See
+    // `ActionBudget`.
+    BudgetExceeded {
+        reactor: EntityId,
+        reaction: Reaction,
+        outcome: ActionOutcome,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentKind {
+    Allegiance,
+    Armor,
+    Health,
+    Position,
+    Reactions,
+}
+
+impl std::fmt::Display for ActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActionError::SourceMissing => f.write_str("the source does not exist"),
+            ActionError::TargetMissing => f.write_str("the target does not exist"),
+            ActionError::ComponentMissing(component_kind) => f.write_fmt(format_args!(
+                "the target has no {component_kind:?} component"
+            )),
+            ActionError::EntityDestroyed => f.write_str("the entity is destroyed"),
+            ActionError::EntityLimitReached => f.write_str("every entity index is in use"),
+            ActionError::BudgetExceeded {
+                reactor, reaction, ..
+            } => f.write_fmt(format_args!(
+                "the action budget ran out at the {reaction:?} reaction of entity {reactor:?}"
+            )),
+        }
+    }
+}
+
+impl std::error::Error for ActionError {}
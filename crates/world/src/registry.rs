@@ -0,0 +1,257 @@
+use crate::{EntityId, EntitySet, Rng};
+
+// Every entity index in use, along with the generation and spawn sequence of the entity that uses
+// it, or used it last. Indices of destroyed entities are reused, lowest first, with the next
+// generation, so ids held after an entity is destroyed never refer to the entity that reuses its
+// index. Indices whose generation cannot grow any further are retired instead.
+pub struct Registry {
+    alive: EntitySet,
+    free: EntitySet,
+    // Index 0 belongs to the world entity.
+    generations: Vec<u32>,
+    // Increases with every spawn, so it keeps the spawn order when indices are reused.
+    spawn_sequences: Vec<u64>,
+    next_spawn_sequence: u64,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new(vec![0], vec![0], EntitySet::default())
+    }
+}
+
+impl Registry {
+    pub fn new(generations: Vec<u32>, spawn_sequences: Vec<u64>, alive: EntitySet) -> Self {
+        let free = (1..generations.len())
+            .filter(|index| !alive.contains_index(*index) && generations[*index] < u32::MAX)
+            .collect();
+
+        // The last spawned entity has the highest sequence, whether it is alive or not.
+        let next_spawn_sequence = spawn_sequences
+            .iter()
+            .max()
+            .map_or(0, |sequence| sequence + 1);
+
+        Self {
+            alive,
+            free,
+            generations,
+            spawn_sequences,
+            next_spawn_sequence,
+        }
+    }
+
+    pub fn generations(&self) -> &Vec<u32> {
+        &self.generations
+    }
+
+    pub fn spawn_sequences(&self) -> &Vec<u64> {
+        &self.spawn_sequences
+    }
+
+    pub fn next_spawn_sequence(&self) -> u64 {
+        self.next_spawn_sequence
+    }
+
+    // Returns the spawn sequence of the entity that uses the index, or used it last.
+    pub fn spawn_sequence(&self, entity: &EntityId) -> u64 {
+        self.spawn_sequences
+            .get(entity.index)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn alive(&self) -> &EntitySet {
+        &self.alive
+    }
+
+    // Returns the index the next spawned entity uses, or None if every index below the entity set
+    // capacity is in use.
+    pub fn next_index(&self) -> Option<usize> {
+        let free_index = self.free.indices().next();
+        let len = self.generations.len();
+
+        free_index.or((len < EntitySet::capacity()).then_some(len))
+    }
+
+    // Returns None if every index below the entity set capacity is in use.
+    pub fn spawn(&mut self) -> Option<EntityId> {
+        let index = self.next_index()?;
+
+        // Free indices never have the last generation, see `release`.
+        if index < self.generations.len() {
+            self.free.remove_index(index);
+            self.generations[index] += 1;
+            self.spawn_sequences[index] = self.next_spawn_sequence;
+        } else {
+            self.generations.push(0);
+            self.spawn_sequences.push(self.next_spawn_sequence);
+        }
+
+        self.next_spawn_sequence += 1;
+        self.alive.insert_index(index);
+        Some(self.resolve(index))
+    }
+
+    pub fn destroy(&mut self, entity: &EntityId) {
+        if self.contains(entity) {
+            self.alive.remove(entity);
+            self.release(entity.index);
+        }
+    }
+
+    // Makes the index free for reuse, unless its generation cannot grow any further, which
+    // retires it for good.
+    fn release(&mut self, index: usize) {
+        if self.generations[index].checked_add(1).is_some() {
+            self.free.insert_index(index);
+        }
+    }
+
+    // Sets the number of indices in use or used before, and the sequence of the next spawn. Added
+    // indices are free, with generation 0.
+    pub fn resize(&mut self, len: usize, next_spawn_sequence: u64) {
+        for index in len..self.generations.len() {
+            self.alive.remove_index(index);
+            self.free.remove_index(index);
+        }
+
+        for index in self.generations.len()..len {
+            self.free.insert_index(index);
+        }
+
+        self.generations.resize(len, 0);
+        self.spawn_sequences.resize(len, 0);
+        self.next_spawn_sequence = next_spawn_sequence;
+    }
+
+    // Sets the generation and spawn sequence of the index, and whether the entity with that
+    // generation is alive.
+    pub fn set(&mut self, index: usize, generation: u32, spawn_sequence: u64, alive: bool) {
+        self.generations[index] = generation;
+        self.spawn_sequences[index] = spawn_sequence;
+
+        if alive {
+            self.alive.insert_index(index);
+            self.free.remove_index(index);
+        } else {
+            self.alive.remove_index(index);
+            self.free.remove_index(index);
+            self.release(index);
+        }
+    }
+
+    // Returns whether the entity is alive. Ids of destroyed entities are not, even if their index
+    // was reused.
+    pub fn contains(&self, entity: &EntityId) -> bool {
+        self.alive.contains(entity)
+            && self.generations.get(entity.index) == Some(&entity.generation)
+    }
+
+    // Returns whether the entity was ever spawned, including the world entity.
+    pub fn spawned(&self, entity: &EntityId) -> bool {
+        self.generations
+            .get(entity.index)
+            .is_some_and(|generation| entity.generation <= *generation)
+    }
+
+    // Returns the id of the entity that uses the index, or used it last.
+    pub fn resolve(&self, index: usize) -> EntityId {
+        EntityId {
+            index,
+            generation: self.generations[index],
+        }
+    }
+
+    // Returns the ids of the entities in the set, which must be alive, in index order. This is not
+    // the spawn order, since indices are reused.
+    pub fn iter<'a>(&'a self, entities: &'a EntitySet) -> impl Iterator<Item = EntityId> + 'a {
+        entities.indices().map(|index| self.resolve(index))
+    }
+
+    // Returns the entities of the set that match the predicate.
+    pub fn filter(&self, entities: &EntitySet, predicate: impl Fn(&EntityId) -> bool) -> EntitySet {
+        self.iter(entities)
+            .filter(|entity| predicate(entity))
+            .collect()
+    }
+
+    // Picks an entity of the set uniformly at random, or returns None if the set is empty.
+    pub fn choose(&self, entities: &EntitySet, rng: &mut Rng) -> Option<EntityId> {
+        let len = entities.indices().count();
+
+        if len == 0 {
+            return None;
+        }
+
+        self.iter(entities).nth(rng.below(len as u64) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, ActionError, Health, Lifecycle, World, WorldConfig, WORLD_ENTITY};
+
+    #[test]
+    fn reused_index_gets_the_next_generation() {
+        let mut registry = Registry::default();
+        let first = registry.spawn().unwrap();
+        let first_sequence = registry.spawn_sequence(&first);
+        registry.destroy(&first);
+        let second = registry.spawn().unwrap();
+
+        assert_eq!(second.index, first.index);
+        assert_eq!(second.generation, first.generation + 1);
+        assert!(!registry.contains(&first));
+        assert!(registry.contains(&second));
+        assert!(registry.spawn_sequence(&second) > first_sequence);
+    }
+
+    #[test]
+    fn stale_id_is_refused_after_its_index_is_reused() {
+        let config = WorldConfig {
+            quiet: true,
+            ..Default::default()
+        };
+
+        let mut world = World::with_config(|_, _| {}, config);
+
+        let spawn = |world: &mut World<_>| {
+            let action = Action::Spawn {
+                allegiance: None,
+                armor: None,
+                health: Some(Health { current: 5, max: 5 }),
+                position: None,
+                reactions: Vec::new(),
+                reaction_priority: 0,
+            };
+
+            world
+                .perform(action, WORLD_ENTITY, WORLD_ENTITY)
+                .unwrap()
+                .spawned()
+                .next()
+                .unwrap()
+        };
+
+        let stale = spawn(&mut world);
+        world.perform(Action::Destroy, stale, stale).unwrap();
+        let entity = spawn(&mut world);
+        assert_eq!(entity.index, stale.index);
+
+        let result = world.perform(Action::Damage { amount: 1 }, WORLD_ENTITY, stale);
+        assert!(matches!(result, Err(ActionError::EntityDestroyed)));
+
+        let result = world.perform(Action::Damage { amount: 1 }, stale, entity);
+        assert!(matches!(result, Err(ActionError::EntityDestroyed)));
+
+        let snapshot = world.snapshot();
+        assert_eq!(world.lifecycle(&stale), Some(Lifecycle::Destroyed));
+        assert_eq!(snapshot.entity(&stale), None);
+        assert_eq!(
+            snapshot.entity(&entity).unwrap().health,
+            Some(Health { current: 5, max: 5 })
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{
+    Action, EntityId, EntityQuery, Notification, SnapshotError, World, WorldConfig, WorldSnapshot,
+};
+
+// Every top-level action performed since the log was started, along with the state it started
+// from. Replaying the log in a fresh world reproduces the state of the world that recorded it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct ActionLog {
+    pub start: WorldSnapshot,
+    pub actions: Vec<LoggedAction>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct LoggedAction {
+    pub action: Action,
+    pub source: EntityId,
+    pub target: Target,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub enum Target {
+    // Performed with `perform`.
+    Entity(EntityId),
+    // Performed with `perform_with_query`.
+    Query(EntityQuery),
+}
+
+impl<NotificationHandler> World<NotificationHandler>
+where
+    NotificationHandler: Fn(EntityId, Notification) + Clone,
+{
+    // Builds a world from the start of the log and performs every logged action in order. Errors
+    // returned by the actions are part of the recording and are not reported. Returns an error if
+    // the log starts from an invalid snapshot.
+    pub fn replay(
+        notification_handler: NotificationHandler,
+        config: WorldConfig,
+        log: &ActionLog,
+    ) -> Result<Self, SnapshotError> {
+        let mut world = Self::with_config(notification_handler, config);
+        world.restore(log.start.clone())?;
+
+        for logged_action in &log.actions {
+            let _ = match &logged_action.target {
+                Target::Entity(target) => {
+                    world.perform(logged_action.action.clone(), logged_action.source, *target)
+                }
+                Target::Query(query) => world.perform_with_query(
+                    logged_action.action.clone(),
+                    logged_action.source,
+                    query.clone(),
+                ),
+            };
+        }
+
+        Ok(world)
+    }
+}
+
+impl<NotificationHandler> World<NotificationHandler>
+where
+    NotificationHandler: Fn(EntityId, Notification),
+{
+    // Returns the recorded actions, if `WorldConfig::record_actions` is enabled.
+    pub fn action_log(&self) -> Option<&ActionLog> {
+        self.action_log.as_ref()
+    }
+
+    // Replays the recorded actions in a fresh world and checks that it ends up in the same state.
+    // Returns false if actions are not being recorded.
+    pub fn verify_replay(&self) -> bool {
+        let Some(log) = &self.action_log else {
+            return false;
+        };
+
+        // The replay resolves the same actions again, so it does not print them.
+        let config = WorldConfig {
+            quiet: true,
+            ..self.config
+        };
+
+        World::replay(|_, _| {}, config, log).is_ok_and(|world| world.snapshot() == self.snapshot())
+    }
+
+    pub(crate) fn log_action(&mut self, action: Action, source: EntityId, target: Target) {
+        if let Some(log) = &mut self.action_log {
+            log.actions.push(LoggedAction {
+                action,
+                source,
+                target,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Allegiance, Armor, Health, Position, Reaction, WORLD_ENTITY};
+
+    fn spawn(
+        world: &mut World<impl Fn(EntityId, Notification)>,
+        allegiance: Allegiance,
+        armor: i64,
+        health: Health,
+        reaction: Reaction,
+    ) -> EntityId {
+        world
+            .perform(
+                Action::Spawn {
+                    allegiance: Some(allegiance),
+                    armor: Some(Armor { current: armor }),
+                    health: Some(health),
+                    position: Some(Position { x: 0, y: 0 }),
+                    reactions: vec![reaction],
+                    reaction_priority: 0,
+                },
+                WORLD_ENTITY,
+                WORLD_ENTITY,
+            )
+            .unwrap()
+            .spawned()
+            .next()
+            .unwrap()
+    }
+
+    // The example from the README: the golem moves away from the player, takes an opportunity
+    // attack, and reinforces its armor.
+    #[test]
+    fn verify_replay_of_readme_example() {
+        let config = WorldConfig {
+            record_actions: true,
+            quiet: true,
+            ..Default::default()
+        };
+
+        let mut world = World::with_config(|_, _| {}, config);
+
+        spawn(
+            &mut world,
+            Allegiance::Player,
+            10,
+            Health {
+                current: 10,
+                max: 10,
+            },
+            Reaction::OpportunityAttack { damage_amount: 3 },
+        );
+
+        let golem = spawn(
+            &mut world,
+            Allegiance::Golem,
+            2,
+            Health { current: 2, max: 3 },
+            Reaction::Reinforce { armor_amount: 3 },
+        );
+
+        world
+            .perform(
+                Action::Move {
+                    to_position: Position { x: 0, y: 1 },
+                },
+                golem,
+                golem,
+            )
+            .unwrap();
+
+        let golem_snapshot = world.snapshot().entity(&golem).cloned().unwrap();
+        assert_eq!(golem_snapshot.health, Some(Health { current: 1, max: 3 }));
+        assert_eq!(golem_snapshot.armor, Some(Armor { current: 3 }));
+        assert_eq!(golem_snapshot.position, Some(Position { x: 0, y: 1 }));
+
+        assert!(world.verify_replay());
+
+        let log = world.action_log().unwrap();
+        let replayed = World::replay(|_, _| {}, config, log).unwrap();
+        assert_eq!(replayed.state_hash(), world.state_hash());
+    }
+}
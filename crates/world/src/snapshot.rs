@@ -0,0 +1,284 @@
+use std::hash::{Hash, Hasher};
+
+use crate::{
+    systems::components::*, EntityId, EntitySet, Notification, Registry, Rng, StableHasher, World,
+    WORLD_ENTITY,
+};
+
+// Every entity and its components, plus the entity generations and spawn sequences, and the random
+// number generator.
+// Restoring a snapshot recreates the exact world state it was taken from.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorldSnapshot {
+    // The generation of every entity index in use, or used before.
+    pub generations: Vec<u32>,
+    // The spawn sequence of the entity that uses every index, or used it last.
+    pub spawn_sequences: Vec<u64>,
+    pub rng: Rng,
+    // Ordered by entity id.
+    pub entities: Vec<EntitySnapshot>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntitySnapshot {
+    pub id: EntityId,
+    pub allegiance: Option<Allegiance>,
+    // The allegiance to revert to after a temporary allegiance change.
+    pub original_allegiance: Option<Allegiance>,
+    pub armor: Option<Armor>,
+    pub health: Option<Health>,
+    pub position: Option<Position>,
+    pub reactions: Vec<Reaction>,
+    pub reaction_priority: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    // The world entity index is not listed, or has a generation other than 0.
+    WorldEntityMissing,
+    // The generations and spawn sequences list a different number of indices.
+    LengthMismatch,
+    // More indices are listed than entity sets can hold. See the `bitset-256` feature.
+    CapacityExceeded,
+    // The entity uses the world entity index, an index that is not listed, or a generation other
+    // than the one listed for its index.
+    InvalidEntity(EntityId),
+    // The entities are not ordered by id, or an index is used twice.
+    EntitiesUnordered,
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::WorldEntityMissing => f.write_str("the world entity is missing"),
+            SnapshotError::LengthMismatch => {
+                f.write_str("the generations and spawn sequences have different lengths")
+            }
+            SnapshotError::CapacityExceeded => {
+                f.write_str("more indices are listed than entity sets can hold")
+            }
+            SnapshotError::InvalidEntity(entity) => f.write_fmt(format_args!(
+                "entity {entity:?} does not match the listed generations"
+            )),
+            SnapshotError::EntitiesUnordered => f.write_str("the entities are not ordered by id"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl WorldSnapshot {
+    pub fn entity(&self, entity: &EntityId) -> Option<&EntitySnapshot> {
+        find_entity(&self.entities, entity)
+    }
+
+    // Checks that the snapshot describes a state the world can be in, which snapshots taken by a
+    // world always do.
+    pub fn validate(&self) -> Result<(), SnapshotError> {
+        if self.generations.first() != Some(&WORLD_ENTITY.generation) {
+            return Err(SnapshotError::WorldEntityMissing);
+        }
+
+        if self.generations.len() != self.spawn_sequences.len() {
+            return Err(SnapshotError::LengthMismatch);
+        }
+
+        if self.generations.len() > EntitySet::capacity() {
+            return Err(SnapshotError::CapacityExceeded);
+        }
+
+        for entity in &self.entities {
+            let id = entity.id;
+
+            if id.index == WORLD_ENTITY.index
+                || self.generations.get(id.index) != Some(&id.generation)
+            {
+                return Err(SnapshotError::InvalidEntity(id));
+            }
+        }
+
+        if self
+            .entities
+            .windows(2)
+            .any(|pair| pair[0].id.index >= pair[1].id.index)
+        {
+            return Err(SnapshotError::EntitiesUnordered);
+        }
+
+        Ok(())
+    }
+}
+
+// Finds the entity in a list ordered by entity id.
+pub(crate) fn find_entity<'a>(
+    entities: &'a [EntitySnapshot],
+    entity: &EntityId,
+) -> Option<&'a EntitySnapshot> {
+    entities
+        .binary_search_by_key(&entity.index, |other| other.id.index)
+        .ok()
+        .map(|index| &entities[index])
+        .filter(|other| other.id == *entity)
+}
+
+impl<NotificationHandler> World<NotificationHandler>
+where
+    NotificationHandler: Fn(EntityId, Notification),
+{
+    pub fn snapshot(&self) -> WorldSnapshot {
+        let entities = self
+            .registry
+            .iter(self.registry.alive())
+            .map(|entity| self.entity_snapshot(entity))
+            .collect();
+
+        WorldSnapshot {
+            generations: self.registry.generations().clone(),
+            spawn_sequences: self.registry.spawn_sequences().clone(),
+            rng: self.rng,
+            entities,
+        }
+    }
+
+    // A hash of the state captured by a snapshot. It only depends on the world state, so worlds in
+    // the same state have the same hash on every platform.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        self.snapshot().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Replaces the world state with the snapshot. Current entities are notified as destroyed, and
+    // restored entities are notified as spawned. A recorded action log starts over from the
+    // snapshot, and the undo history is cleared. An invalid snapshot leaves the world unchanged.
+    pub fn restore(&mut self, snapshot: WorldSnapshot) -> Result<(), SnapshotError> {
+        snapshot.validate()?;
+        self.restore_valid(snapshot);
+        Ok(())
+    }
+
+    // Restores a snapshot known to be valid, like one the world took itself.
+    pub(crate) fn restore_valid(&mut self, snapshot: WorldSnapshot) {
+        if let Some(log) = &mut self.action_log {
+            log.start = snapshot.clone();
+            log.actions.clear();
+        }
+
+        if let Some(history) = &mut self.history {
+            *history = Default::default();
+        }
+
+        let entities: Vec<_> = self.registry.iter(self.registry.alive()).collect();
+
+        for entity in entities {
+            self.remove_entity(entity);
+        }
+
+        self.registry = Registry::new(
+            snapshot.generations,
+            snapshot.spawn_sequences,
+            snapshot.entities.iter().map(|entity| entity.id).collect(),
+        );
+        self.rng = snapshot.rng;
+        self.chain = Default::default();
+
+        for entity in snapshot.entities {
+            self.insert_entity(entity);
+        }
+    }
+
+    pub(crate) fn entity_snapshot(&self, entity: EntityId) -> EntitySnapshot {
+        EntitySnapshot {
+            id: entity,
+            allegiance: self.allegiance_system.allegiance(&entity).copied(),
+            original_allegiance: self.allegiance_system.original_allegiance(&entity).copied(),
+            armor: self.armor_system.armor(&entity).copied(),
+            health: self.health_system.health(&entity).copied(),
+            position: self.position_system.position(&entity).copied(),
+            reactions: self
+                .reaction_system
+                .reactions(&entity)
+                .cloned()
+                .unwrap_or_default(),
+            reaction_priority: self.reaction_system.reaction_priority(&entity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Action;
+
+    fn spawn(world: &mut World<impl Fn(EntityId, Notification)>) -> EntityId {
+        world
+            .perform(
+                Action::Spawn {
+                    allegiance: None,
+                    armor: None,
+                    health: None,
+                    position: None,
+                    reactions: Vec::new(),
+                    reaction_priority: 0,
+                },
+                WORLD_ENTITY,
+                WORLD_ENTITY,
+            )
+            .unwrap()
+            .spawned()
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn restore_rejects_invalid_snapshots() {
+        let mut world = World::new(|_, _| {});
+        spawn(&mut world);
+        let valid = world.snapshot();
+
+        let mut shorter_sequences = valid.clone();
+        shorter_sequences.spawn_sequences.pop();
+
+        let mut stale_entity = valid.clone();
+        stale_entity.entities[0].id.generation += 1;
+
+        let mut unknown_entity = valid.clone();
+        unknown_entity.entities[0].id.index = valid.generations.len();
+
+        let mut duplicate_entity = valid.clone();
+        duplicate_entity.entities.push(valid.entities[0].clone());
+
+        let mut no_world_entity = valid.clone();
+        no_world_entity.generations.clear();
+        no_world_entity.spawn_sequences.clear();
+
+        let mut too_many_indices = valid.clone();
+        too_many_indices.generations = vec![0; EntitySet::capacity() + 1];
+        too_many_indices.spawn_sequences = vec![0; EntitySet::capacity() + 1];
+
+        let cases = [
+            (shorter_sequences, SnapshotError::LengthMismatch),
+            (
+                stale_entity.clone(),
+                SnapshotError::InvalidEntity(stale_entity.entities[0].id),
+            ),
+            (
+                unknown_entity.clone(),
+                SnapshotError::InvalidEntity(unknown_entity.entities[0].id),
+            ),
+            (duplicate_entity, SnapshotError::EntitiesUnordered),
+            (no_world_entity, SnapshotError::WorldEntityMissing),
+            (too_many_indices, SnapshotError::CapacityExceeded),
+        ];
+
+        for (snapshot, error) in cases {
+            assert_eq!(world.restore(snapshot), Err(error));
+            assert_eq!(world.snapshot(), valid);
+        }
+
+        assert_eq!(world.restore(valid.clone()), Ok(()));
+        assert_eq!(world.snapshot(), valid);
+    }
+}
@@ -16,4 +16,5 @@ pub mod components {
     pub use super::Health;
     pub use super::Position;
     pub use super::Reaction;
+    pub use super::ReactionKind;
 }
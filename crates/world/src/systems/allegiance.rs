@@ -1,27 +1,32 @@
-use crate::{EntityId, EntityMap, EntitySet, Notification};
+use crate::{EntityId, EntityIndex, EntityMap, EntitySet, Notification, Notifier};
 
-#[cfg_attr(debug_assertions, derive(Debug))]
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Allegiance {
     Player,
     Golem,
 }
 
 pub struct AllegianceSystem<NotificationHandler> {
-    _notification_handler: NotificationHandler,
+    notification_handler: Notifier<NotificationHandler>,
     pub entities: EntitySet,
     allegiance_map: EntityMap<Allegiance>,
+    allegiance_index: EntityIndex<Allegiance>,
+    // Allegiances to restore for entities whose allegiance was changed temporarily.
+    original_allegiance_map: EntityMap<Allegiance>,
 }
 
 impl<NotificationHandler> AllegianceSystem<NotificationHandler>
 where
     NotificationHandler: Fn(EntityId, Notification),
 {
-    pub fn new(notification_handler: NotificationHandler) -> Self {
+    pub fn new(notification_handler: Notifier<NotificationHandler>) -> Self {
         Self {
-            _notification_handler: notification_handler,
+            notification_handler,
             entities: Default::default(),
             allegiance_map: Default::default(),
+            allegiance_index: Default::default(),
+            original_allegiance_map: Default::default(),
         }
     }
 
@@ -29,29 +34,66 @@ where
         self.allegiance_map.get(entity)
     }
 
+    // Returns the allegiance that a temporary allegiance change will revert to.
+    pub fn original_allegiance(&self, entity: &EntityId) -> Option<&Allegiance> {
+        self.original_allegiance_map.get(entity)
+    }
+
     pub fn entities(&self, allegiances: &[Allegiance]) -> EntitySet {
-        if allegiances.is_empty() {
-            return Default::default();
-        }
+        self.allegiance_index.entities(allegiances)
+    }
+
+    // Returns the previous allegiance. A temporary change keeps the original allegiance to revert
+    // to, while a permanent change discards it.
+    pub fn change(
+        &mut self,
+        entity: EntityId,
+        allegiance: Allegiance,
+        temporary: bool,
+    ) -> Option<Allegiance> {
+        let current_allegiance = self.allegiance_map.get_mut(&entity)?;
+        let previous_allegiance = std::mem::replace(current_allegiance, allegiance);
 
-        let mut entities = EntitySet::default();
+        self.allegiance_index.remove(&previous_allegiance, &entity);
+        self.allegiance_index.insert(allegiance, &entity);
 
-        for (entity, allegiance) in &self.allegiance_map {
-            if allegiances.contains(allegiance) {
-                entities.insert(entity);
+        match temporary {
+            true => {
+                self.original_allegiance_map
+                    .entry(entity)
+                    .or_insert(previous_allegiance);
+            }
+            false => {
+                self.original_allegiance_map.remove(&entity);
             }
         }
 
-        entities
+        self.notification_handler.notify(
+            entity,
+            Notification::ChangeAllegiance(Some(current_allegiance)),
+        );
+
+        Some(previous_allegiance)
     }
 
     pub fn insert(&mut self, entity: EntityId, allegiance: Allegiance) {
+        self.remove(&entity);
+
         self.entities.insert(&entity);
         self.allegiance_map.insert(entity, allegiance);
+        self.allegiance_index.insert(allegiance, &entity);
+    }
+
+    pub fn insert_original(&mut self, entity: EntityId, allegiance: Allegiance) {
+        self.original_allegiance_map.insert(entity, allegiance);
     }
 
     pub fn remove(&mut self, entity: &EntityId) {
+        if let Some(allegiance) = self.allegiance_map.remove(entity) {
+            self.allegiance_index.remove(&allegiance, entity);
+        }
+
         self.entities.remove(entity);
-        self.allegiance_map.remove(entity);
+        self.original_allegiance_map.remove(entity);
     }
 }
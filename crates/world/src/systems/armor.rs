@@ -1,11 +1,11 @@
-use crate::{EntityId, EntityMap, Notification};
+use crate::{EntityId, EntityMap, EntitySet, Notification, Notifier};
 
-#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Armor {
     pub current: i64,
 }
 
-#[cfg(debug_assertions)]
 impl std::fmt::Debug for Armor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.current.fmt(f)
@@ -13,7 +13,8 @@ impl std::fmt::Debug for Armor {
 }
 
 pub struct ArmorSystem<NotificationHandler> {
-    notification_handler: NotificationHandler,
+    notification_handler: Notifier<NotificationHandler>,
+    pub entities: EntitySet,
     armor_map: EntityMap<Armor>,
 }
 
@@ -21,32 +22,31 @@ impl<NotificationHandler> ArmorSystem<NotificationHandler>
 where
     NotificationHandler: Fn(EntityId, Notification),
 {
-    pub fn new(notification_handler: NotificationHandler) -> Self {
+    pub fn new(notification_handler: Notifier<NotificationHandler>) -> Self {
         Self {
             notification_handler,
+            entities: Default::default(),
             armor_map: Default::default(),
         }
     }
 
-    pub fn _armor(&self, entity: &EntityId) -> Option<&Armor> {
+    pub fn armor(&self, entity: &EntityId) -> Option<&Armor> {
         self.armor_map.get(entity)
     }
 
-    pub fn gain(&mut self, entity: EntityId, amount: i64) {
-        let Some(armor) = self.armor_map.get_mut(&entity) else {
-            return;
-        };
-
+    // Returns the updated armor.
+    pub fn gain(&mut self, entity: EntityId, amount: i64) -> Option<Armor> {
+        let armor = self.armor_map.get_mut(&entity)?;
         armor.current += amount;
-        (self.notification_handler)(entity, Notification::ChangeArmor(Some(armor)));
+
+        self.notification_handler
+            .notify(entity, Notification::ChangeArmor(Some(armor)));
+        Some(*armor)
     }
 
     // Returns amount of overflow damage.
     pub fn lose(&mut self, entity: EntityId, amount: i64) -> Option<i64> {
-        let Some(armor) = self.armor_map.get_mut(&entity) else {
-            return None;
-        };
-
+        let armor = self.armor_map.get_mut(&entity)?;
         armor.current -= amount;
 
         let overflow_damage = match armor.current < 0 {
@@ -58,15 +58,18 @@ where
             false => Some(0),
         };
 
-        (self.notification_handler)(entity, Notification::ChangeArmor(Some(armor)));
+        self.notification_handler
+            .notify(entity, Notification::ChangeArmor(Some(armor)));
         overflow_damage
     }
 
     pub fn insert(&mut self, entity: EntityId, armor: Armor) {
+        self.entities.insert(&entity);
         self.armor_map.insert(entity, armor);
     }
 
     pub fn remove(&mut self, entity: &EntityId) {
+        self.entities.remove(entity);
         self.armor_map.remove(entity);
     }
 }
@@ -1,12 +1,12 @@
-use crate::{EntityId, EntityMap, Notification};
+use crate::{EntityId, EntityMap, EntitySet, Notification, Notifier};
 
-#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Health {
     pub current: i64,
     pub max: i64,
 }
 
-#[cfg(debug_assertions)]
 impl std::fmt::Debug for Health {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("{}/{}", self.current, self.max))
@@ -14,7 +14,8 @@ impl std::fmt::Debug for Health {
 }
 
 pub struct HealthSystem<NotificationHandler> {
-    notification_handler: NotificationHandler,
+    notification_handler: Notifier<NotificationHandler>,
+    pub entities: EntitySet,
     health_map: EntityMap<Health>,
 }
 
@@ -22,34 +23,67 @@ impl<NotificationHandler> HealthSystem<NotificationHandler>
 where
     NotificationHandler: Fn(EntityId, Notification),
 {
-    pub fn new(notification_handler: NotificationHandler) -> Self {
+    pub fn new(notification_handler: Notifier<NotificationHandler>) -> Self {
         Self {
             notification_handler,
+            entities: Default::default(),
             health_map: Default::default(),
         }
     }
 
-    pub fn _health(&self, entity: &EntityId) -> Option<&Health> {
+    pub fn health(&self, entity: &EntityId) -> Option<&Health> {
         self.health_map.get(entity)
     }
 
-    // Returns whether the entity is alive.
-    pub fn lose(&mut self, entity: EntityId, amount: i64) -> Option<bool> {
-        let Some(health) = self.health_map.get_mut(&entity) else {
-            return None;
-        };
+    // Returns the updated health.
+    pub fn lose(&mut self, entity: EntityId, amount: i64) -> Option<Health> {
+        let health = self.health_map.get_mut(&entity)?;
+        let before = *health;
+        health.current = health.current.saturating_sub(amount);
 
-        health.current -= amount;
+        self.notify_change(entity, before)
+    }
+
+    // Returns the updated health. Health is never restored beyond its max.
+    pub fn gain(&mut self, entity: EntityId, amount: i64) -> Option<Health> {
+        let health = self.health_map.get_mut(&entity)?;
+        let before = *health;
+        health.current = health
+            .current
+            .max(health.current.saturating_add(amount).min(health.max));
+
+        self.notify_change(entity, before)
+    }
+
+    // Returns the updated health. Current health is clamped to the new max.
+    pub fn modify_max(&mut self, entity: EntityId, amount: i64) -> Option<Health> {
+        let health = self.health_map.get_mut(&entity)?;
+        let before = *health;
+        health.max = health.max.saturating_add(amount);
+        health.current = health.current.min(health.max);
+
+        self.notify_change(entity, before)
+    }
+
+    // Notifies the health of the entity if it differs from the health before, and returns it.
+    fn notify_change(&self, entity: EntityId, before: Health) -> Option<Health> {
+        let health = self.health_map.get(&entity)?;
+
+        if *health != before {
+            self.notification_handler
+                .notify(entity, Notification::ChangeHealth(Some(health)));
+        }
 
-        (self.notification_handler)(entity, Notification::ChangeHealth(Some(health)));
-        Some(health.current > 0)
+        Some(*health)
     }
 
     pub fn insert(&mut self, entity: EntityId, health: Health) {
+        self.entities.insert(&entity);
         self.health_map.insert(entity, health);
     }
 
     pub fn remove(&mut self, entity: &EntityId) {
+        self.entities.remove(entity);
         self.health_map.remove(entity);
     }
 }
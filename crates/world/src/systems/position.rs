@@ -1,12 +1,12 @@
-use crate::{EntityId, EntityMap, EntitySet, Notification};
+use crate::{EntityId, EntityIndex, EntityMap, EntitySet, Notification, Notifier};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Position {
     pub x: i64,
     pub y: i64,
 }
 
-#[cfg(debug_assertions)]
 impl std::fmt::Debug for Position {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_tuple("").field(&self.x).field(&self.y).finish()
@@ -14,20 +14,22 @@ impl std::fmt::Debug for Position {
 }
 
 pub struct PositionSystem<NotificationHandler> {
-    notification_handler: NotificationHandler,
+    notification_handler: Notifier<NotificationHandler>,
     pub entities: EntitySet,
     position_map: EntityMap<Position>,
+    position_index: EntityIndex<Position>,
 }
 
 impl<NotificationHandler> PositionSystem<NotificationHandler>
 where
     NotificationHandler: Fn(EntityId, Notification),
 {
-    pub fn new(notification_handler: NotificationHandler) -> Self {
+    pub fn new(notification_handler: Notifier<NotificationHandler>) -> Self {
         Self {
             notification_handler,
             entities: Default::default(),
             position_map: Default::default(),
+            position_index: Default::default(),
         }
     }
 
@@ -36,19 +38,12 @@ where
     }
 
     pub fn entities(&self, positions: &[Position]) -> EntitySet {
-        if positions.is_empty() {
-            return Default::default();
-        }
-
-        let mut entities = EntitySet::default();
-
-        for (entity, position) in &self.position_map {
-            if positions.contains(position) {
-                entities.insert(entity);
-            }
-        }
+        self.position_index.entities(positions)
+    }
 
-        entities
+    // Returns the entities at every position that matches the predicate.
+    pub fn entities_where(&self, predicate: impl Fn(&Position) -> bool + Clone) -> EntitySet {
+        self.position_index.entities_where(predicate)
     }
 
     pub fn move_to(&mut self, entity: EntityId, position: Position) {
@@ -56,18 +51,27 @@ where
             return;
         };
 
+        self.position_index.remove(current_position, &entity);
+        self.position_index.insert(position, &entity);
         *current_position = position;
 
-        (self.notification_handler)(entity, Notification::ChangePosition(Some(current_position)));
+        self.notification_handler
+            .notify(entity, Notification::ChangePosition(Some(current_position)));
     }
 
     pub fn insert(&mut self, entity: EntityId, position: Position) {
+        self.remove(&entity);
+
         self.entities.insert(&entity);
         self.position_map.insert(entity, position);
+        self.position_index.insert(position, &entity);
     }
 
     pub fn remove(&mut self, entity: &EntityId) {
+        if let Some(position) = self.position_map.remove(entity) {
+            self.position_index.remove(&position, entity);
+        }
+
         self.entities.remove(entity);
-        self.position_map.remove(entity);
     }
 }
@@ -1,42 +1,255 @@
-use crate::{EntityId, EntityMap, Notification};
+use std::{cmp::Reverse, collections::HashMap, rc::Rc};
 
-#[cfg_attr(debug_assertions, derive(Debug))]
-#[derive(Clone, Copy)]
+use crate::{EntityId, EntityMap, EntitySet, EventKind, Notification, Notifier};
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Reaction {
     OpportunityAttack { damage_amount: i64 },
     Reinforce { armor_amount: i64 },
     Spite { damage_amount: i64 },
+    Root,
+    Ward { damage_reduction: i64 },
+    Immortal,
+    Empathy { armor_amount: i64 },
+    DivineShield,
+    Loyalty { damage_amount: i64 },
+    Volatile { damage_amount: i64 },
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReactionKind {
+    OpportunityAttack,
+    Reinforce,
+    Spite,
+    Root,
+    Ward,
+    Immortal,
+    Empathy,
+    DivineShield,
+    Loyalty,
+    Volatile,
 }
 
+impl Reaction {
+    pub fn kind(&self) -> ReactionKind {
+        match self {
+            Reaction::OpportunityAttack { .. } => ReactionKind::OpportunityAttack,
+            Reaction::Reinforce { .. } => ReactionKind::Reinforce,
+            Reaction::Spite { .. } => ReactionKind::Spite,
+            Reaction::Root => ReactionKind::Root,
+            Reaction::Ward { .. } => ReactionKind::Ward,
+            Reaction::Immortal => ReactionKind::Immortal,
+            Reaction::Empathy { .. } => ReactionKind::Empathy,
+            Reaction::DivineShield => ReactionKind::DivineShield,
+            Reaction::Loyalty { .. } => ReactionKind::Loyalty,
+            Reaction::Volatile { .. } => ReactionKind::Volatile,
+        }
+    }
+
+    // Reactions with a higher priority resolve first. The priority belongs to the kind, because
+    // reactions that change or prevent their event must resolve before the reactions that depend
+    // on the outcome, such as Immortal before Spite. Reactions of equal priority are ordered by the
+    // reaction priority of their reactors, then the tiebreaker.
+    pub fn priority(&self) -> i64 {
+        match self {
+            Reaction::OpportunityAttack { .. } => 0,
+            Reaction::Reinforce { .. } => 0,
+            Reaction::Spite { .. } => 0,
+            Reaction::Root => 1,
+            Reaction::Ward { .. } => 1,
+            Reaction::Immortal => 1,
+            Reaction::Empathy { .. } => 0,
+            Reaction::DivineShield => 1,
+            Reaction::Loyalty { .. } => 0,
+            Reaction::Volatile { .. } => 0,
+        }
+    }
+
+    // Returns the kind of event that the reaction responds to.
+    pub fn trigger(&self) -> EventKind {
+        match self {
+            Reaction::OpportunityAttack { .. } => EventKind::AfterMove,
+            Reaction::Reinforce { .. } => EventKind::AfterDamage,
+            Reaction::Spite { .. } => EventKind::BeforeDestroy,
+            Reaction::Root => EventKind::BeforeMove,
+            Reaction::Ward { .. } => EventKind::BeforeDamage,
+            Reaction::Immortal => EventKind::BeforeDestroy,
+            Reaction::Empathy { .. } => EventKind::AfterHeal,
+            Reaction::DivineShield => EventKind::BeforeDamage,
+            Reaction::Loyalty { .. } => EventKind::AfterChangeAllegiance,
+            Reaction::Volatile { .. } => EventKind::BeforeDestroy,
+        }
+    }
+}
+
+pub type Subscriptions = Rc<Vec<(EntityId, Reaction)>>;
+
 pub struct ReactionSystem<NotificationHandler> {
-    _notification_handler: NotificationHandler,
-    pub reactions_map: EntityMap<Vec<Reaction>>,
+    notification_handler: Notifier<NotificationHandler>,
+    pub entities: EntitySet,
+    reactions_map: EntityMap<Vec<Reaction>>,
+    // The reaction priority and spawn sequence of every entity, which order it among other reactors.
+    reactor_map: EntityMap<(i64, u64)>,
+    // Reactions grouped by the event kind they respond to, ordered by descending priority, then
+    // descending reaction priority of the reactor, then spawn order, then their order on the
+    // reactor. Emitting an event holds a clone of the `Rc`, so changes made while it resolves copy
+    // the list instead of affecting the event.
+    subscriptions_map: HashMap<EventKind, Subscriptions>,
 }
 
 impl<NotificationHandler> ReactionSystem<NotificationHandler>
 where
     NotificationHandler: Fn(EntityId, Notification),
 {
-    pub fn new(notification_handler: NotificationHandler) -> Self {
+    pub fn new(notification_handler: Notifier<NotificationHandler>) -> Self {
         Self {
-            _notification_handler: notification_handler,
+            notification_handler,
+            entities: Default::default(),
             reactions_map: Default::default(),
+            reactor_map: Default::default(),
+            subscriptions_map: Default::default(),
         }
     }
 
-    pub fn _reactions(&self, entity: &EntityId) -> Option<&Vec<Reaction>> {
+    pub fn subscriptions(&self, event_kind: EventKind) -> Option<Subscriptions> {
+        self.subscriptions_map.get(&event_kind).cloned()
+    }
+
+    fn subscribe(&mut self, entity: EntityId, reaction: Reaction) {
+        let reactor_map = &self.reactor_map;
+
+        let key = |(reactor, reaction): &(EntityId, Reaction)| {
+            let (reaction_priority, spawn_sequence) =
+                reactor_map.get(reactor).copied().unwrap_or_default();
+
+            (
+                Reverse(reaction.priority()),
+                Reverse(reaction_priority),
+                spawn_sequence,
+            )
+        };
+        let subscription = (entity, reaction);
+
+        let subscriptions = Rc::make_mut(
+            self.subscriptions_map
+                .entry(reaction.trigger())
+                .or_default(),
+        );
+
+        let index = subscriptions.partition_point(|other| key(other) <= key(&subscription));
+        subscriptions.insert(index, subscription);
+    }
+
+    fn unsubscribe(&mut self, entity: EntityId, reaction: Reaction) {
+        let Some(subscriptions) = self.subscriptions_map.get_mut(&reaction.trigger()) else {
+            return;
+        };
+
+        let subscription = (entity, reaction);
+
+        if let Some(index) = subscriptions
+            .iter()
+            .position(|other| other == &subscription)
+        {
+            Rc::make_mut(subscriptions).remove(index);
+        }
+    }
+
+    pub fn reactions(&self, entity: &EntityId) -> Option<&Vec<Reaction>> {
         self.reactions_map.get(entity)
     }
 
-    pub fn insert(&mut self, entity: EntityId, reactions: Vec<Reaction>) {
+    pub fn reaction_priority(&self, entity: &EntityId) -> i64 {
+        self.reactor_map
+            .get(entity)
+            .map_or(0, |(reaction_priority, _)| *reaction_priority)
+    }
+
+    pub fn insert(
+        &mut self,
+        entity: EntityId,
+        reactions: Vec<Reaction>,
+        reaction_priority: i64,
+        spawn_sequence: u64,
+    ) {
+        self.remove(&entity);
+        self.reactor_map
+            .insert(entity, (reaction_priority, spawn_sequence));
+
         if reactions.is_empty() {
             return;
         }
 
+        for reaction in &reactions {
+            self.subscribe(entity, *reaction);
+        }
+
+        self.entities.insert(&entity);
         self.reactions_map.insert(entity, reactions);
     }
 
+    pub fn add(&mut self, entity: EntityId, reaction: Reaction) {
+        self.subscribe(entity, reaction);
+        self.entities.insert(&entity);
+
+        let reactions = self.reactions_map.entry(entity).or_default();
+        reactions.push(reaction);
+
+        self.notification_handler
+            .notify(entity, Notification::ChangeReactions(reactions));
+    }
+
+    // Removes the first matching reaction. Returns whether the entity had the reaction.
+    pub fn remove_reaction(&mut self, entity: EntityId, reaction: Reaction) -> bool {
+        let Some(reactions) = self.reactions_map.get_mut(&entity) else {
+            return false;
+        };
+
+        let Some(index) = reactions.iter().position(|other| other == &reaction) else {
+            return false;
+        };
+
+        reactions.remove(index);
+        self.notification_handler
+            .notify(entity, Notification::ChangeReactions(reactions));
+
+        if reactions.is_empty() {
+            self.entities.remove(&entity);
+            self.reactions_map.remove(&entity);
+        }
+
+        self.unsubscribe(entity, reaction);
+        true
+    }
+
+    pub fn clear(&mut self, entity: EntityId) {
+        let Some(reactions) = self.reactions_map.remove(&entity) else {
+            return;
+        };
+
+        self.entities.remove(&entity);
+
+        for reaction in reactions {
+            self.unsubscribe(entity, reaction);
+        }
+
+        self.notification_handler
+            .notify(entity, Notification::ChangeReactions(&Vec::new()));
+    }
+
     pub fn remove(&mut self, entity: &EntityId) {
-        self.reactions_map.remove(entity);
+        self.reactor_map.remove(entity);
+
+        let Some(reactions) = self.reactions_map.remove(entity) else {
+            return;
+        };
+
+        self.entities.remove(entity);
+
+        for reaction in reactions {
+            self.unsubscribe(*entity, reaction);
+        }
     }
 }
@@ -0,0 +1,210 @@
+use crate::{find_entity, EntityId, EntitySnapshot, Notification, World, WORLD_ENTITY};
+
+impl<NotificationHandler> World<NotificationHandler>
+where
+    NotificationHandler: Fn(EntityId, Notification),
+{
+    // Runs the closure as a single batch of actions. If it returns an error, everything done since
+    // the transaction started is rolled back, including reactions, the random number generator and
+    // recorded actions, and none of its notifications are sent. If it succeeds, the notifications
+    // describe the net change of the whole batch. A failed transaction nested in another one only
+    // rolls back its own changes.
+    pub fn transaction<T, E>(
+        &mut self,
+        transaction: impl FnOnce(&mut Self) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let start = self.snapshot();
+        let logged_actions = self.action_log.as_ref().map(|log| log.actions.len());
+        let history = self.history.clone();
+        let nested = self.notification_handler.muted();
+
+        self.notification_handler.set_muted(true);
+
+        let result = transaction(self);
+
+        if result.is_err() {
+            // Restoring starts the action log over, so it is set aside and truncated instead.
+            let action_log = self.action_log.take();
+            self.restore_valid(start.clone());
+            self.action_log = action_log;
+            self.history = history;
+
+            if let (Some(log), Some(len)) = (&mut self.action_log, logged_actions) {
+                log.actions.truncate(len);
+            }
+        }
+
+        if !nested {
+            self.notification_handler.set_muted(false);
+
+            if result.is_ok() {
+                let end = self.snapshot();
+                self.notify_changes(&start.entities, &end.entities);
+
+                if self.config.notify_state_hash {
+                    self.notification_handler
+                        .notify(WORLD_ENTITY, Notification::StateHash(self.state_hash()));
+                }
+            }
+        }
+
+        result
+    }
+
+    // Notifies the entities destroyed between the states, then the entities spawned or changed, in
+    // id order. Both lists are ordered by entity id.
+    pub(crate) fn notify_changes(&self, from: &[EntitySnapshot], to: &[EntitySnapshot]) {
+        for before in from {
+            if find_entity(to, &before.id).is_none() {
+                self.notification_handler
+                    .notify(before.id, Notification::Destroy);
+            }
+        }
+
+        for after in to {
+            let Some(before) = find_entity(from, &after.id) else {
+                self.notification_handler.notify(
+                    after.id,
+                    Notification::Spawn {
+                        allegiance: after.allegiance.as_ref(),
+                        armor: after.armor.as_ref(),
+                        health: after.health.as_ref(),
+                        position: after.position.as_ref(),
+                        reactions: &after.reactions,
+                    },
+                );
+                continue;
+            };
+
+            self.notify_entity_changes(before, after);
+        }
+    }
+
+    fn notify_entity_changes(&self, before: &EntitySnapshot, after: &EntitySnapshot) {
+        let entity = after.id;
+
+        if before.allegiance != after.allegiance {
+            self.notification_handler.notify(
+                entity,
+                Notification::ChangeAllegiance(after.allegiance.as_ref()),
+            );
+        }
+
+        if before.armor != after.armor {
+            self.notification_handler
+                .notify(entity, Notification::ChangeArmor(after.armor.as_ref()));
+        }
+
+        if before.health != after.health {
+            self.notification_handler
+                .notify(entity, Notification::ChangeHealth(after.health.as_ref()));
+        }
+
+        if before.position != after.position {
+            self.notification_handler.notify(
+                entity,
+                Notification::ChangePosition(after.position.as_ref()),
+            );
+        }
+
+        if before.reactions != after.reactions {
+            self.notification_handler
+                .notify(entity, Notification::ChangeReactions(&after.reactions));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::*;
+
+    fn spawn(world: &mut World<impl Fn(EntityId, Notification)>) -> EntityId {
+        world
+            .perform(
+                Action::Spawn {
+                    allegiance: None,
+                    armor: None,
+                    health: Some(Health { current: 5, max: 5 }),
+                    position: None,
+                    reactions: Vec::new(),
+                    reaction_priority: 0,
+                },
+                WORLD_ENTITY,
+                WORLD_ENTITY,
+            )
+            .unwrap()
+            .spawned()
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn failed_transaction_is_rolled_back_silently() {
+        let notifications = Rc::new(Cell::new(0));
+
+        let config = WorldConfig {
+            record_actions: true,
+            notify_state_hash: true,
+            quiet: true,
+            ..Default::default()
+        };
+
+        let mut world = World::with_config(
+            {
+                let notifications = notifications.clone();
+                move |_, _| notifications.set(notifications.get() + 1)
+            },
+            config,
+        );
+
+        let entity = spawn(&mut world);
+        let state_hash = world.state_hash();
+        notifications.set(0);
+
+        let result = world.transaction(|world| {
+            world.perform(Action::Damage { amount: 2 }, WORLD_ENTITY, entity)?;
+            spawn(world);
+            world.perform(Action::Destroy, WORLD_ENTITY, WORLD_ENTITY)
+        });
+
+        assert!(matches!(result, Err(ActionError::TargetMissing)));
+        assert_eq!(notifications.get(), 0);
+        assert_eq!(world.state_hash(), state_hash);
+        assert_eq!(world.action_log().unwrap().actions.len(), 1);
+        assert!(world.verify_replay());
+    }
+
+    #[test]
+    fn committed_transaction_notifies_the_net_change() {
+        let notifications = Rc::new(Cell::new(0));
+
+        let config = WorldConfig {
+            quiet: true,
+            ..Default::default()
+        };
+
+        let mut world = World::with_config(
+            {
+                let notifications = notifications.clone();
+                move |_, _| notifications.set(notifications.get() + 1)
+            },
+            config,
+        );
+
+        let entity = spawn(&mut world);
+        notifications.set(0);
+
+        world
+            .transaction(|world| {
+                world.perform(Action::Damage { amount: 2 }, WORLD_ENTITY, entity)?;
+                world.perform(Action::Heal { amount: 2 }, WORLD_ENTITY, entity)?;
+                world.perform(Action::Damage { amount: 1 }, WORLD_ENTITY, entity)
+            })
+            .unwrap();
+
+        // One health change, instead of one per action.
+        assert_eq!(notifications.get(), 1);
+    }
+}
@@ -1,49 +1,165 @@
 use std::{
     collections::HashMap,
-    hash::{BuildHasherDefault, Hasher},
+    hash::{BuildHasherDefault, Hash, Hasher},
 };
 
-use hi_sparse_bitset::{config::_128bit, iter::CachingIndexIter, ops::And, reduce, BitSet};
+use hi_sparse_bitset::{
+    apply,
+    cache::DynamicCache,
+    config::Config,
+    iter::CachingIndexIter,
+    ops::{And, Or, Sub},
+    reduce_w_cache, BitSet,
+};
 
 use crate::EntityId;
 
-#[derive(Default)]
+#[cfg(feature = "bitset-256")]
+type EntitySetConfig = hi_sparse_bitset::config::_256bit;
+#[cfg(not(feature = "bitset-256"))]
+type EntitySetConfig = hi_sparse_bitset::config::_128bit;
+
+#[derive(Default, Clone)]
 #[repr(transparent)]
-pub struct EntitySet(BitSet<_128bit>);
+pub struct EntitySet(BitSet<EntitySetConfig>);
 
 impl EntitySet {
+    // Entity indices must be below the capacity.
+    pub fn capacity() -> usize {
+        EntitySetConfig::max_value()
+    }
+
+    // Returns an empty set if no sets are given.
     pub fn intersection(sets: &[Option<&Self>]) -> Self {
         let bitsets = sets.iter().filter_map(|set| set.map(|set| &set.0));
-        Self(BitSet::from_iter(reduce(And, bitsets).unwrap()))
+
+        match reduce_w_cache(And, bitsets, DynamicCache) {
+            Some(intersection) => Self(BitSet::from_iter(intersection)),
+            None => Default::default(),
+        }
+    }
+
+    pub fn union<'a>(sets: impl Iterator<Item = &'a Self> + Clone) -> Self {
+        match reduce_w_cache(Or, sets.map(|set| &set.0), DynamicCache) {
+            Some(union) => Self(BitSet::from_iter(union)),
+            None => Default::default(),
+        }
+    }
+
+    // Returns the entities of this set that are not in the other set.
+    pub fn difference(&self, other: &Self) -> Self {
+        Self(BitSet::from_iter(apply(Sub, &self.0, &other.0)))
     }
 
     pub fn insert(&mut self, entity: &EntityId) {
-        self.0.insert(entity.0)
+        self.insert_index(entity.index)
     }
 
     pub fn remove(&mut self, entity: &EntityId) -> bool {
-        self.0.remove(entity.0)
+        self.remove_index(entity.index)
+    }
+
+    // Ignores the generation, so the caller must make sure the entity is alive.
+    pub fn contains(&self, entity: &EntityId) -> bool {
+        self.contains_index(entity.index)
+    }
+
+    pub fn insert_index(&mut self, index: usize) {
+        self.0.insert(index)
+    }
+
+    pub fn remove_index(&mut self, index: usize) -> bool {
+        self.0.remove(index)
+    }
+
+    pub fn contains_index(&self, index: usize) -> bool {
+        self.0.contains(index)
     }
 
-    pub fn iter(&self) -> EntitySetIter {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // Entity ids are resolved by the `Registry`.
+    pub fn indices(&self) -> EntitySetIter<'_> {
         EntitySetIter(self.0.iter())
     }
 }
 
 impl<const N: usize> From<[EntityId; N]> for EntitySet {
     fn from(value: [EntityId; N]) -> Self {
-        Self(BitSet::from_iter(value.into_iter().map(|entity| entity.0)))
+        Self(BitSet::from_iter(
+            value.into_iter().map(|entity| entity.index),
+        ))
+    }
+}
+
+impl FromIterator<EntityId> for EntitySet {
+    fn from_iter<I: IntoIterator<Item = EntityId>>(iter: I) -> Self {
+        Self(BitSet::from_iter(
+            iter.into_iter().map(|entity| entity.index),
+        ))
+    }
+}
+
+impl FromIterator<usize> for EntitySet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        Self(BitSet::from_iter(iter))
     }
 }
 
 #[repr(transparent)]
-pub struct EntitySetIter<'a>(CachingIndexIter<&'a BitSet<_128bit>>);
+pub struct EntitySetIter<'a>(CachingIndexIter<&'a BitSet<EntitySetConfig>>);
 
 impl<'a> Iterator for EntitySetIter<'a> {
-    type Item = EntityId;
+    type Item = usize;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(EntityId)
+        self.0.next()
+    }
+}
+
+// Reverse index from component values to the entities that have them.
+pub struct EntityIndex<K>(HashMap<K, EntitySet>);
+
+impl<K> Default for EntityIndex<K> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<K> EntityIndex<K>
+where
+    K: Eq + Hash,
+{
+    pub fn entities(&self, keys: &[K]) -> EntitySet {
+        EntitySet::union(keys.iter().filter_map(|key| self.0.get(key)))
+    }
+
+    // Returns the entities of every key that matches the predicate.
+    pub fn entities_where(&self, predicate: impl Fn(&K) -> bool + Clone) -> EntitySet {
+        EntitySet::union(
+            self.0
+                .iter()
+                .filter(move |(key, _)| predicate(key))
+                .map(|(_, entities)| entities),
+        )
+    }
+
+    pub fn insert(&mut self, key: K, entity: &EntityId) {
+        self.0.entry(key).or_default().insert(entity)
+    }
+
+    pub fn remove(&mut self, key: &K, entity: &EntityId) {
+        let Some(entities) = self.0.get_mut(key) else {
+            return;
+        };
+
+        entities.remove(entity);
+
+        if entities.is_empty() {
+            self.0.remove(key);
+        }
     }
 }
 
@@ -67,28 +183,69 @@ impl Hasher for EntityHasher {
     }
 }
 
-#[macro_export]
-macro_rules! log_with_indentation {
-    ($indentation_level: expr, $fmt: expr) => {
-        #[cfg(debug_assertions)]
-        {
-            print!(
-                "{}",
-                (0..$indentation_level).map(|_| "\t").collect::<String>()
-            );
+// 64-bit FNV-1a. Integers are written as little-endian 64-bit values, so hashes are the same on
+// every platform.
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
 
-            println!($fmt)
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
         }
-    };
-    ($indentation_level: expr, $fmt: expr, $($args:tt)*) => {
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+}
+
+// Prints the message in debug builds, indented by the stack depth, unless the world is quiet.
+#[macro_export]
+macro_rules! log_with_indentation {
+    ($world: expr, $indentation_level: expr, $($args:tt)*) => {
         #[cfg(debug_assertions)]
-        {
+        if !$world.config.quiet {
             print!(
                 "{}",
                 (0..$indentation_level).map(|_| "\t").collect::<String>()
             );
 
-            println!($fmt, $args)
+            println!($($args)*)
         }
     };
 }